@@ -0,0 +1,362 @@
+use crate::backup;
+use crate::history::History;
+use crate::job::{JobQueue, Priority, SubmitError};
+use crate::monitor::SessionError;
+use crate::supervisor::Control;
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use log::{debug, error};
+use premises_config::v1;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::error;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const WORKERS: usize = 4;
+const MAX_BODY_LEN: u64 = 64 * 1024;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize)]
+struct CommandRequest {
+    command: String,
+}
+
+#[derive(Deserialize)]
+struct RestoreRequest {
+    backup: String,
+}
+
+/// HTTP/JSON API to watch and control the server.
+#[derive(Clone)]
+pub struct Api {
+    history: Arc<Mutex<History>>,
+    jobs: JobQueue,
+    control_tx: Sender<Control>,
+    backup_dir: PathBuf,
+    token: Option<String>,
+}
+
+fn error_body(message: impl ToString) -> Value {
+    json!({ "error": message.to_string() })
+}
+
+impl Api {
+    pub fn new(
+        history: Arc<Mutex<History>>,
+        jobs: JobQueue,
+        control_tx: Sender<Control>,
+        backup_dir: PathBuf,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            history,
+            jobs,
+            control_tx,
+            backup_dir,
+            token,
+        }
+    }
+
+    fn is_authorized(&self, request: &Request) -> bool {
+        if self.token.is_none() {
+            return true;
+        }
+        request.headers().iter().any(|header| {
+            header.field.equiv("Authorization") && self.accepts(header.value.as_str())
+        })
+    }
+
+    /// Checks the value of an Authorization header. The token is compared in
+    /// constant time not to tell how much of it matched.
+    fn accepts(&self, authorization: &str) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        match authorization.strip_prefix("Bearer ") {
+            Some(given) => given.as_bytes().ct_eq(token.as_bytes()).into(),
+            None => false,
+        }
+    }
+
+    fn status(&self) -> Value {
+        let history = self.history.lock().unwrap();
+        json!({
+            "state": history.state,
+            "health": history.health,
+            "status": history.status,
+            "metrics": history.metrics,
+        })
+    }
+
+    fn events(&self, query: Option<&str>) -> (u16, Value) {
+        let mut since = 0;
+        for param in query.unwrap_or_default().split('&') {
+            if let Some(value) = param.strip_prefix("since=") {
+                match value.parse() {
+                    Ok(value) => since = value,
+                    Err(_) => return (400, error_body("Invalid since")),
+                }
+            }
+        }
+
+        let history = self.history.lock().unwrap();
+        let events: Vec<_> = history
+            .since(since)
+            .map(|(id, event)| {
+                let time = event.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                json!({
+                    "id": id,
+                    "time": time.as_millis() as u64,
+                    "event": event.kind,
+                })
+            })
+            .collect();
+        (200, json!({ "events": events }))
+    }
+
+    fn command(&self, body: &str) -> (u16, Value) {
+        let request: CommandRequest = match serde_json::from_str(body) {
+            Ok(request) => request,
+            Err(err) => return (400, error_body(err)),
+        };
+
+        let ticket = match self.jobs.submit(request.command, Priority::Normal) {
+            Ok(ticket) => ticket,
+            Err(err @ SubmitError::QueueFull) => return (429, error_body(err)),
+            Err(err @ SubmitError::Closed) => return (500, error_body(err)),
+        };
+
+        let (code, mut body) = match ticket.reply.recv_timeout(COMMAND_TIMEOUT) {
+            Ok(Ok(output)) => (200, json!({ "output": output })),
+            Ok(Err(
+                err @ (SessionError::Disabled | SessionError::Backoff | SessionError::Connect(_)),
+            )) => (503, error_body(err)),
+            Ok(Err(err)) => (502, error_body(err)),
+            Err(RecvTimeoutError::Timeout) => (504, error_body("Timed out")),
+            Err(RecvTimeoutError::Disconnected) => (500, error_body(SubmitError::Closed)),
+        };
+        body["job_id"] = json!(ticket.id);
+        (code, body)
+    }
+
+    fn control(&self, control: Control) -> (u16, Value) {
+        self.control_tx.send(control).unwrap();
+        (202, json!({}))
+    }
+
+    fn backup(&self) -> (u16, Value) {
+        match self.jobs.backup() {
+            Ok(id) => (202, json!({ "job_id": id })),
+            // The backup already waiting to start covers this request too.
+            Err(SubmitError::QueueFull) => (202, json!({})),
+            Err(err @ SubmitError::Closed) => (500, error_body(err)),
+        }
+    }
+
+    fn backups(&self) -> (u16, Value) {
+        match backup::list(&self.backup_dir) {
+            Ok(manifests) => (200, json!({ "backups": manifests })),
+            Err(err) => (500, error_body(err)),
+        }
+    }
+
+    fn restore(&self, body: &str) -> (u16, Value) {
+        let request: RestoreRequest = match serde_json::from_str(body) {
+            Ok(request) => request,
+            Err(err) => return (400, error_body(err)),
+        };
+        match backup::list(&self.backup_dir) {
+            Ok(manifests) if manifests.iter().any(|m| m.name == request.backup) => {
+                self.control(Control::Restore(request.backup))
+            }
+            Ok(_) => (404, error_body("Backup not found")),
+            Err(err) => (500, error_body(err)),
+        }
+    }
+
+    fn route(&self, method: &Method, url: &str, body: &str) -> (u16, Value) {
+        let (path, query) = match url.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (url, None),
+        };
+
+        match (method, path) {
+            (Method::Get, "/status") => (200, self.status()),
+            (Method::Get, "/events") => self.events(query),
+            (Method::Post, "/command") => self.command(body),
+            (Method::Post, "/start") => self.control(Control::Start),
+            (Method::Post, "/stop") => self.control(Control::Stop),
+            (Method::Post, "/restart") => self.control(Control::Restart),
+            (Method::Post, "/backup") => self.backup(),
+            (Method::Get, "/backups") => self.backups(),
+            (Method::Post, "/restore") => self.restore(body),
+            (
+                _,
+                "/status" | "/events" | "/command" | "/start" | "/stop" | "/restart" | "/backup"
+                | "/backups" | "/restore",
+            ) => (405, error_body("Method not allowed")),
+            _ => (404, error_body("Not found")),
+        }
+    }
+
+    fn handle(&self, mut request: Request) {
+        let (code, body) = if !self.is_authorized(&request) {
+            (401, error_body("Unauthorized"))
+        } else {
+            let mut body = String::new();
+            match request
+                .as_reader()
+                .take(MAX_BODY_LEN)
+                .read_to_string(&mut body)
+            {
+                Ok(_) => self.route(request.method(), request.url(), &body),
+                Err(err) => (400, error_body(err)),
+            }
+        };
+        debug!("{} {} -> {}", request.method(), request.url(), code);
+
+        let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+        let response = Response::from_string(body.to_string())
+            .with_status_code(code)
+            .with_header(content_type);
+        if let Err(err) = request.respond(response) {
+            debug!("Failed to send response: {}", err);
+        }
+    }
+}
+
+/// Serves the API in the background.
+pub fn start(
+    config: &v1::Api,
+    api: Api,
+) -> Result<(), Box<dyn error::Error + Send + Sync + 'static>> {
+    let server = Arc::new(Server::http(&config.bind)?);
+    for _ in 0..WORKERS {
+        let server = server.clone();
+        let api = api.clone();
+        thread::spawn(move || loop {
+            match server.recv() {
+                Ok(request) => api.handle(request),
+                Err(err) => error!("Failed to accept API request: {}", err),
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::{self, BackupJob, JobReceiver, Next};
+    use crate::lifecycle::State;
+    use crate::monitor::{Event, EventKind};
+    use crossbeam_channel::{self as channel, Receiver};
+
+    fn api() -> (Api, JobReceiver, Receiver<Control>, Receiver<BackupJob>) {
+        let (jobs, job_rx, backup_rx) = job::queue();
+        let (control_tx, control_rx) = channel::unbounded();
+        let history = Arc::new(Mutex::new(History::new(10)));
+        let backup_dir = PathBuf::from("/nonexistent/backups");
+        (
+            Api::new(history, jobs, control_tx, backup_dir, None),
+            job_rx,
+            control_rx,
+            backup_rx,
+        )
+    }
+
+    #[test]
+    fn test_accepts() {
+        let (mut api, _, _, _) = api();
+        assert!(api.accepts(""));
+
+        api.token = Some("secret".to_string());
+        assert!(api.accepts("Bearer secret"));
+        assert!(!api.accepts("Bearer secreT"));
+        assert!(!api.accepts("Bearer secret2"));
+        assert!(!api.accepts("secret"));
+        assert!(!api.accepts(""));
+    }
+
+    #[test]
+    fn test_status_and_events() {
+        let (api, _, _, _) = api();
+        {
+            let mut history = api.history.lock().unwrap();
+            history.push(Event::new(EventKind::StateChanged(State::Preparing)));
+            history.push(Event::new(EventKind::StateChanged(State::Starting(42))));
+        }
+
+        let (code, body) = api.route(&Method::Get, "/status", "");
+        assert_eq!(200, code);
+        assert_eq!(json!({ "starting": 42 }), body["state"]);
+
+        let (code, body) = api.route(&Method::Get, "/events?since=1", "");
+        assert_eq!(200, code);
+        let events = body["events"].as_array().unwrap();
+        assert_eq!(1, events.len());
+        assert_eq!(2, events[0]["id"]);
+        assert_eq!(
+            json!({ "state_changed": { "starting": 42 } }),
+            events[0]["event"]
+        );
+
+        let (code, _) = api.route(&Method::Get, "/events?since=x", "");
+        assert_eq!(400, code);
+    }
+
+    #[test]
+    fn test_command() {
+        let (api, job_rx, _, _) = api();
+        thread::spawn(move || {
+            let Some(Next::Job(job)) = job_rx.next(&channel::never()) else {
+                panic!("expected a job");
+            };
+            assert_eq!("list", job.command);
+            job.reply
+                .send(Ok("There are 0 of a max of 20 players online: ".to_string()))
+                .unwrap();
+        });
+
+        let (code, body) = api.route(&Method::Post, "/command", r#"{"command": "list"}"#);
+        assert_eq!(200, code);
+        assert_eq!(
+            "There are 0 of a max of 20 players online: ",
+            body["output"]
+        );
+
+        let (code, _) = api.route(&Method::Post, "/command", "list");
+        assert_eq!(400, code);
+    }
+
+    #[test]
+    fn test_control() {
+        let (api, _, control_rx, _) = api();
+        assert_eq!(202, api.route(&Method::Post, "/restart", "").0);
+        assert_eq!(Control::Restart, control_rx.try_recv().unwrap());
+        assert_eq!(405, api.route(&Method::Get, "/stop", "").0);
+        assert_eq!(404, api.route(&Method::Get, "/", "").0);
+    }
+
+    #[test]
+    fn test_backup() {
+        let (api, _, _, backup_rx) = api();
+        let (code, body) = api.route(&Method::Post, "/backup", "");
+        assert_eq!(202, code);
+        assert_eq!(202, api.route(&Method::Post, "/backup", "").0);
+        let queued: Vec<_> = backup_rx.try_iter().map(|job| json!(job.id)).collect();
+        assert_eq!(vec![body["job_id"].clone()], queued);
+
+        let (code, body) = api.route(&Method::Get, "/backups", "");
+        assert_eq!(200, code);
+        assert_eq!(json!({ "backups": [] }), body);
+
+        let (code, _) = api.route(&Method::Post, "/restore", r#"{"backup": "backup-1"}"#);
+        assert_eq!(404, code);
+    }
+}
@@ -0,0 +1,633 @@
+use crate::history::History;
+use crate::job::{BackupJob, JobQueue, Priority, SubmitError};
+use crate::lifecycle::{Lifecycle, State};
+use crate::monitor::{Event, EventKind, SessionError};
+use crossbeam_channel::{self as channel, select, Receiver, RecvTimeoutError, Sender};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use guardian::command::{Command, SaveAll, SaveOff, SaveOn};
+use guardian::properties::Properties;
+use log::{debug, error, info, warn};
+use premises_config::v1;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fmt::{self, Write as _};
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, prelude::*, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time to wait for the server to respond to commands around a backup.
+/// Flushing a large world can take a while.
+const SAVE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time a command around a backup may wait for the jobs ahead of it.
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(10);
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+/// Directory in the working directory to move worlds to while restoring.
+const RESTORE_ASIDE_DIR: &str = "pre-restore";
+
+/// Description of a backup, stored as `<name>.json` next to the archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub name: String,
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    /// Version of the server, if it was seen running.
+    pub version: Option<String>,
+    /// World directories in the archive, relative to the working directory.
+    pub worlds: Vec<String>,
+    /// Size of the archive in bytes.
+    pub size: u64,
+    /// SHA-256 of the archive in hex.
+    pub sha256: String,
+}
+
+impl Manifest {
+    pub fn archive_name(&self) -> String {
+        format!("{}.tar.gz", self.name)
+    }
+
+    fn manifest_name(&self) -> String {
+        format!("{}.json", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The server is starting or stopping, so the world cannot be saved.
+    Busy(State),
+    Submit(SubmitError),
+    Session(SessionError),
+    Timeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Busy(state) => write!(f, "Server is busy ({:?})", state),
+            Self::Submit(err) => write!(f, "{}", err),
+            Self::Session(err) => write!(f, "{}", err),
+            Self::Timeout => write!(f, "Server did not respond in time"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Passes written bytes through while hashing and counting them.
+struct HashWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.update(&buf[..len]);
+        self.size += len as u64;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).unwrap();
+    }
+    hex
+}
+
+/// Lists the world directories of the server: the one named by `level-name`
+/// and the other dimensions the vanilla server keeps next to it.
+pub fn world_dirs(work_dir: &Path) -> io::Result<Vec<String>> {
+    let level_name = match fs::read_to_string(work_dir.join("server.properties")) {
+        Ok(text) => Properties::parse(&text)
+            .get("level-name")
+            .unwrap_or("world")
+            .to_string(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => "world".to_string(),
+        Err(err) => return Err(err),
+    };
+
+    Ok([
+        format!("{}_nether", level_name),
+        format!("{}_the_end", level_name),
+        level_name,
+    ]
+    .into_iter()
+    .filter(|world| work_dir.join(world).is_dir())
+    .collect())
+}
+
+/// Writes a gzipped tarball of `worlds` to `path`, returning its size and
+/// checksum.
+fn write_archive(path: &Path, work_dir: &Path, worlds: &[String]) -> io::Result<(u64, String)> {
+    let writer = HashWriter {
+        inner: BufWriter::new(File::create(path)?),
+        hasher: Sha256::new(),
+        size: 0,
+    };
+    let mut builder = tar::Builder::new(GzEncoder::new(writer, Compression::default()));
+    for world in worlds {
+        builder.append_dir_all(world, work_dir.join(world))?;
+    }
+
+    let mut writer = builder.into_inner()?.finish()?;
+    writer.flush()?;
+    writer
+        .inner
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()?;
+    Ok((writer.size, to_hex(&writer.hasher.finalize())))
+}
+
+/// Archives `worlds` in `work_dir` into `dir` along with a manifest.
+fn create_archive(
+    work_dir: &Path,
+    dir: &Path,
+    worlds: Vec<String>,
+    version: Option<String>,
+    time: SystemTime,
+) -> io::Result<Manifest> {
+    create_dir_all(dir)?;
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let name = format!("backup-{}", since_epoch.as_millis());
+
+    // Only complete archives get their final name.
+    let partial_path = dir.join(format!("{}.tar.gz.partial", name));
+    let (size, sha256) = match write_archive(&partial_path, work_dir, &worlds) {
+        Ok(result) => result,
+        Err(err) => {
+            let _ = fs::remove_file(&partial_path);
+            return Err(err);
+        }
+    };
+
+    let manifest = Manifest {
+        name,
+        time: since_epoch.as_secs(),
+        version,
+        worlds,
+        size,
+        sha256,
+    };
+    fs::rename(&partial_path, dir.join(manifest.archive_name()))?;
+
+    let tmp_path = dir.join(format!("{}.tmp", manifest.manifest_name()));
+    fs::write(&tmp_path, serde_json::to_vec_pretty(&manifest)?)?;
+    fs::rename(&tmp_path, dir.join(manifest.manifest_name()))?;
+    Ok(manifest)
+}
+
+/// Reads the manifests of the backups in `dir`, newest first.
+pub fn list(dir: &Path) -> io::Result<Vec<Manifest>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let mut manifests = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+        match serde_json::from_slice::<Manifest>(&fs::read(&path)?) {
+            Ok(manifest) => manifests.push(manifest),
+            Err(err) => warn!("Ignoring invalid manifest {}: {}", path.display(), err),
+        }
+    }
+    manifests.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| b.name.cmp(&a.name)));
+    Ok(manifests)
+}
+
+/// Picks the backups the retention rules no longer keep out of `manifests`
+/// sorted newest first. Days and weeks are in UTC, and weeks start on Monday.
+fn expired<'a>(manifests: &'a [Manifest], retention: &v1::Retention) -> Vec<&'a Manifest> {
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    manifests
+        .iter()
+        .enumerate()
+        .filter(|(i, manifest)| {
+            let day = manifest.time / SECS_PER_DAY;
+            // The Unix epoch was on a Thursday.
+            let week = (day + 3) / 7;
+            // Only the newest backup of each day and week is kept for it.
+            let daily = days.len() < retention.keep_daily && days.insert(day);
+            let weekly = weeks.len() < retention.keep_weekly && weeks.insert(week);
+            *i >= retention.keep_last && !daily && !weekly
+        })
+        .map(|(_, manifest)| manifest)
+        .collect()
+}
+
+/// Deletes the backups in `dir` the retention rules no longer keep.
+fn prune(dir: &Path, retention: &v1::Retention) -> io::Result<()> {
+    let manifests = list(dir)?;
+    for manifest in expired(&manifests, retention) {
+        info!("Deleting backup {}", manifest.name);
+        match fs::remove_file(dir.join(manifest.archive_name())) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+        fs::remove_file(dir.join(manifest.manifest_name()))?;
+    }
+    Ok(())
+}
+
+/// Reads the manifest of backup `name` in `dir`.
+fn read_manifest(dir: &Path, name: &str) -> io::Result<Manifest> {
+    // The name may come from the API, so keep it inside `dir`.
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid backup name: {:?}", name),
+        ));
+    }
+    let manifest = fs::read(dir.join(format!("{}.json", name)))?;
+    Ok(serde_json::from_slice(&manifest)?)
+}
+
+/// Checks that the archive of `manifest` is intact.
+fn verify(dir: &Path, manifest: &Manifest) -> io::Result<()> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(
+        &mut File::open(dir.join(manifest.archive_name()))?,
+        &mut hasher,
+    )?;
+    if size != manifest.size || to_hex(&hasher.finalize()) != manifest.sha256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Checksum mismatch in backup {}", manifest.name),
+        ));
+    }
+    Ok(())
+}
+
+/// Worlds moved aside by a restore. They are kept until the next restore so
+/// that the restore can be rolled back.
+#[derive(Debug)]
+pub struct Aside {
+    work_dir: PathBuf,
+    dir: PathBuf,
+    /// Worlds moved aside.
+    worlds: Vec<String>,
+    /// Worlds extracted from the backup.
+    restored: Vec<String>,
+}
+
+impl Aside {
+    fn move_aside(work_dir: &Path, manifest: &Manifest) -> io::Result<Self> {
+        let mut worlds = world_dirs(work_dir)?;
+        for world in &manifest.worlds {
+            if !worlds.contains(world) && work_dir.join(world).exists() {
+                worlds.push(world.clone());
+            }
+        }
+
+        let dir = work_dir.join(RESTORE_ASIDE_DIR);
+        match fs::remove_dir_all(&dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+        create_dir_all(&dir)?;
+        let mut aside = Self {
+            work_dir: work_dir.to_path_buf(),
+            dir,
+            worlds: Vec::new(),
+            restored: Vec::new(),
+        };
+        for world in worlds {
+            if let Err(err) = fs::rename(work_dir.join(&world), aside.dir.join(&world)) {
+                aside.roll_back()?;
+                return Err(err);
+            }
+            aside.worlds.push(world);
+        }
+        aside.restored = manifest.worlds.clone();
+        Ok(aside)
+    }
+
+    /// Replaces the restored worlds with the ones moved aside.
+    pub fn roll_back(&self) -> io::Result<()> {
+        for world in self.restored.iter().chain(&self.worlds) {
+            match fs::remove_dir_all(self.work_dir.join(world)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => (),
+            }
+        }
+        for world in &self.worlds {
+            fs::rename(self.dir.join(world), self.work_dir.join(world))?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates backups of the world, saving it over RCON first while the server
+/// is running.
+#[derive(Debug, Clone)]
+pub struct Backups {
+    work_dir: PathBuf,
+    config: v1::Backup,
+    lifecycle: Lifecycle,
+    /// `None` when RCON is disabled.
+    jobs: Option<JobQueue>,
+    history: Arc<Mutex<History>>,
+    event_tx: Sender<Event>,
+    /// Held while a backup is created.
+    lock: Arc<Mutex<()>>,
+}
+
+impl Backups {
+    pub fn new(
+        config: &v1::Config,
+        lifecycle: Lifecycle,
+        jobs: Option<JobQueue>,
+        history: Arc<Mutex<History>>,
+        event_tx: Sender<Event>,
+    ) -> Self {
+        Self {
+            work_dir: config.work_dir.clone(),
+            config: config.backup.clone(),
+            lifecycle,
+            jobs,
+            history,
+            event_tx,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn dir(&self) -> PathBuf {
+        self.work_dir.join(&self.config.dir)
+    }
+
+    /// Waits for the backup in progress to finish, and keeps others from
+    /// starting until the guard is dropped.
+    pub fn hold(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap()
+    }
+
+    fn run_command<C: Command>(&self, jobs: &JobQueue, command: &C) -> Result<String, Error> {
+        let ticket = jobs
+            .run_with_timeout(command, SAVE_TIMEOUT, Priority::High)
+            .map_err(Error::Submit)?;
+        match ticket.reply.recv_timeout(SAVE_TIMEOUT + QUEUE_TIMEOUT) {
+            Ok(result) => result.map_err(Error::Session),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Submit(SubmitError::Closed)),
+        }
+    }
+
+    fn create(&self) -> Result<Manifest, Error> {
+        let _guard = self.hold();
+        let worlds = world_dirs(&self.work_dir)?;
+        if worlds.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "No world to back up").into());
+        }
+        let version = self.history.lock().unwrap().version.clone();
+        let archive = |worlds| {
+            create_archive(
+                &self.work_dir,
+                &self.dir(),
+                worlds,
+                version,
+                SystemTime::now(),
+            )
+        };
+
+        match self.lifecycle.state() {
+            State::Online => (),
+            state @ (State::Preparing | State::Starting(_) | State::Stopping) => {
+                return Err(Error::Busy(state))
+            }
+            // Nothing writes to the world while the server is not running.
+            _ => return Ok(archive(worlds)?),
+        }
+
+        let Some(jobs) = &self.jobs else {
+            return Err(Error::Session(SessionError::Disabled));
+        };
+        // Keep the server from writing to the world while it is archived.
+        let result = self
+            .run_command(jobs, &SaveOff)
+            .and_then(|_| self.run_command(jobs, &SaveAll { flush: true }))
+            .and_then(|_| Ok(archive(worlds)?));
+        // Even if turning saving off failed, it may have taken effect.
+        if let Err(err) = self.run_command(jobs, &SaveOn) {
+            error!("Failed to turn saving back on: {}", err);
+        }
+        result
+    }
+
+    /// Replaces the worlds with the ones in backup `name` after checking the
+    /// archive. The current worlds are moved aside, and put back if the
+    /// backup cannot be extracted. The server must not be running.
+    pub fn restore(&self, name: &str) -> Result<Aside, Error> {
+        let _guard = self.hold();
+        let dir = self.dir();
+        let manifest = read_manifest(&dir, name)?;
+        verify(&dir, &manifest)?;
+
+        let aside = Aside::move_aside(&self.work_dir, &manifest)?;
+        let extracted = File::open(dir.join(manifest.archive_name()))
+            .and_then(|file| tar::Archive::new(GzDecoder::new(file)).unpack(&self.work_dir));
+        if let Err(err) = extracted {
+            aside.roll_back()?;
+            return Err(err.into());
+        }
+        Ok(aside)
+    }
+
+    /// Creates a backup and deletes the ones no longer kept, reporting the
+    /// result in an event.
+    pub fn run(&self) {
+        let kind = match self.create() {
+            Ok(manifest) => {
+                info!("Created backup {} ({} bytes)", manifest.name, manifest.size);
+                if let Err(err) = prune(&self.dir(), &self.config.retention) {
+                    error!("Failed to delete old backups: {}", err);
+                }
+                EventKind::BackupCreated(manifest)
+            }
+            Err(err) => {
+                error!("Failed to create backup: {}", err);
+                EventKind::BackupFailed(err.to_string())
+            }
+        };
+        self.event_tx.send(Event::new(kind)).unwrap();
+    }
+}
+
+/// Runs the backup jobs, and creates backups periodically while the server is
+/// online if an interval is configured.
+pub fn start_backups(backups: Backups, jobs: Receiver<BackupJob>) {
+    let schedule = match backups.config.interval_secs {
+        Some(secs) => channel::tick(Duration::from_secs(secs)),
+        None => channel::never(),
+    };
+    loop {
+        select! {
+            recv(jobs) -> job => match job {
+                Ok(job) => debug!("Running backup job {}", job.id),
+                Err(_) => return,
+            },
+            recv(schedule) -> _ => if backups.lifecycle.state() != State::Online {
+                debug!("Server is not online; skipping scheduled backup");
+                continue;
+            },
+        }
+        backups.run();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn manifest(name: &str, time: u64) -> Manifest {
+        Manifest {
+            name: name.to_string(),
+            time,
+            version: None,
+            worlds: vec!["world".to_string()],
+            size: 0,
+            sha256: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_create_archive() {
+        let work_dir = std::env::temp_dir().join(format!("guardian-backup-{}", process::id()));
+        create_dir_all(work_dir.join("custom/region")).unwrap();
+        create_dir_all(work_dir.join("custom_nether")).unwrap();
+        create_dir_all(work_dir.join("world")).unwrap();
+        fs::write(work_dir.join("server.properties"), "level-name=custom\n").unwrap();
+        fs::write(work_dir.join("custom/level.dat"), "level").unwrap();
+        fs::write(work_dir.join("custom/region/r.0.0.mca"), "region").unwrap();
+
+        let dir = work_dir.join("backups");
+        let worlds = world_dirs(&work_dir).unwrap();
+        assert_eq!(vec!["custom_nether", "custom"], worlds);
+        let manifest = create_archive(
+            &work_dir,
+            &dir,
+            worlds,
+            Some("1.20.1".to_string()),
+            UNIX_EPOCH + Duration::from_millis(1_690_000_000_123),
+        )
+        .unwrap();
+
+        let archive = fs::read(dir.join(manifest.archive_name())).unwrap();
+        let listed = list(&dir).unwrap();
+        fs::remove_dir_all(&work_dir).unwrap();
+
+        assert_eq!("backup-1690000000123", manifest.name);
+        assert_eq!(1_690_000_000, manifest.time);
+        assert_eq!(archive.len() as u64, manifest.size);
+        assert_eq!(to_hex(&Sha256::digest(&archive)), manifest.sha256);
+        assert_eq!(vec![manifest], listed);
+
+        let mut paths: Vec<_> = tar::Archive::new(GzDecoder::new(archive.as_slice()))
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        paths.sort();
+        assert!(paths.contains(&"custom/level.dat".to_string()));
+        assert!(paths.contains(&"custom/region/r.0.0.mca".to_string()));
+        assert!(paths.iter().all(|path| !path.starts_with("world")));
+    }
+
+    #[test]
+    fn test_restore() {
+        let work_dir = std::env::temp_dir().join(format!("guardian-restore-{}", process::id()));
+        create_dir_all(work_dir.join("world")).unwrap();
+        fs::write(work_dir.join("world/level.dat"), "old").unwrap();
+
+        let config = serde_json::json!({"v1": {
+            "work_dir": work_dir,
+            "server": {"jar": "server.jar"},
+        }});
+        let premises_config::Config::V1(config) = serde_json::from_value(config).unwrap();
+        let (event_tx, _event_rx) = channel::unbounded();
+        let backups = Backups::new(
+            &config,
+            Lifecycle::new(event_tx.clone()),
+            None,
+            Arc::new(Mutex::new(History::new(1))),
+            event_tx,
+        );
+        let manifest = create_archive(
+            &work_dir,
+            &backups.dir(),
+            vec!["world".to_string()],
+            None,
+            SystemTime::now(),
+        )
+        .unwrap();
+        fs::write(work_dir.join("world/level.dat"), "new").unwrap();
+        let read = |path: &str| fs::read_to_string(work_dir.join(path)).unwrap();
+
+        let aside = backups.restore(&manifest.name).unwrap();
+        assert_eq!("old", read("world/level.dat"));
+        assert_eq!("new", read("pre-restore/world/level.dat"));
+        aside.roll_back().unwrap();
+        assert_eq!("new", read("world/level.dat"));
+
+        assert!(backups.restore("../backup").is_err());
+        let archive = backups.dir().join(manifest.archive_name());
+        let mut corrupted = fs::read(&archive).unwrap();
+        corrupted[0] ^= 1;
+        fs::write(&archive, corrupted).unwrap();
+        let result = backups.restore(&manifest.name);
+        let level = read("world/level.dat");
+        fs::remove_dir_all(&work_dir).unwrap();
+
+        assert!(matches!(result, Err(Error::Io(err)) if err.kind() == io::ErrorKind::InvalidData));
+        assert_eq!("new", level);
+    }
+
+    #[test]
+    fn test_expired() {
+        let retention = v1::Retention {
+            keep_last: 2,
+            keep_daily: 2,
+            keep_weekly: 2,
+        };
+        // 1970-01-05 was a Monday.
+        let monday = 4 * SECS_PER_DAY;
+        let manifests = vec![
+            manifest("last", monday + 9 * SECS_PER_DAY + 200),
+            manifest("second", monday + 9 * SECS_PER_DAY + 100),
+            manifest("same-day", monday + 9 * SECS_PER_DAY),
+            manifest("previous-day", monday + 8 * SECS_PER_DAY),
+            manifest("same-week", monday + 7 * SECS_PER_DAY),
+            manifest("previous-week", monday + 6 * SECS_PER_DAY),
+            manifest("old", monday),
+        ];
+
+        let names: Vec<_> = expired(&manifests, &retention)
+            .into_iter()
+            .map(|manifest| manifest.name.as_str())
+            .collect();
+        assert_eq!(vec!["same-day", "same-week", "old"], names);
+    }
+}
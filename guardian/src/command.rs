@@ -0,0 +1,689 @@
+use serde_json::Value;
+use std::fmt;
+
+use crate::rcon::{Error, MAX_COMMAND_LEN};
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Longest message, leaving room in the command line for the command and a
+/// player name.
+const MAX_MESSAGE_LEN: usize = MAX_COMMAND_LEN - 32;
+
+/// A Minecraft command with a typed response.
+///
+/// Arguments are validated when they are constructed, so that a command line
+/// built from them cannot be altered by user input.
+pub trait Command {
+    type Output;
+
+    fn to_command_line(&self) -> String;
+
+    fn parse_response(&self, response: &str) -> Result<Self::Output>;
+}
+
+/// Implements `parse_response` for commands whose response is only a message
+/// to show as it is.
+macro_rules! raw_response {
+    () => {
+        fn parse_response(&self, response: &str) -> Result<String> {
+            Ok(response.to_string())
+        }
+    };
+}
+
+fn unexpected(response: &str) -> Error {
+    Error::UnexpectedResponse(response.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerName(String);
+
+impl PlayerName {
+    pub fn new(name: &str) -> Result<Self> {
+        if name.is_empty()
+            || name.len() > 16
+            || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid player name: {:?}",
+                name
+            )));
+        }
+
+        Ok(Self(name.to_string()))
+    }
+}
+
+impl fmt::Display for PlayerName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Free-form text which fits in a single command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message(String);
+
+impl Message {
+    pub fn new(message: &str) -> Result<Self> {
+        if message.chars().any(char::is_control) {
+            return Err(Error::InvalidArgument(
+                "Message must not contain control characters".to_string(),
+            ));
+        }
+        if message.len() > MAX_MESSAGE_LEN {
+            return Err(Error::InvalidArgument(format!(
+                "Message must not be longer than {} bytes",
+                MAX_MESSAGE_LEN
+            )));
+        }
+
+        Ok(Self(message.to_string()))
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRuleName(String);
+
+impl GameRuleName {
+    pub fn new(name: &str) -> Result<Self> {
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(Error::InvalidArgument(format!(
+                "Invalid game rule: {:?}",
+                name
+            )));
+        }
+
+        Ok(Self(name.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameRuleValue {
+    Bool(bool),
+    Int(i32),
+}
+
+impl fmt::Display for GameRuleValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::Int(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Player(PlayerName),
+    AllPlayers,
+    NearestPlayer,
+    RandomPlayer,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Player(name) => write!(f, "{}", name),
+            Self::AllPlayers => f.write_str("@a"),
+            Self::NearestPlayer => f.write_str("@p"),
+            Self::RandomPlayer => f.write_str("@r"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlayerList {
+    pub online: u32,
+    pub max: u32,
+    pub players: Vec<String>,
+}
+
+/// Result of a command which changes the status of a player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerChange {
+    Changed,
+    /// The player already had the status.
+    Unchanged,
+    /// The player is unknown, or is not online when it has to be.
+    NotFound,
+}
+
+/// Parses the response to a command changing the status of a player, which
+/// starts with `changed` when the change was made.
+fn parse_player_change(response: &str, changed: &str) -> Result<PlayerChange> {
+    if response.starts_with(changed) {
+        Ok(PlayerChange::Changed)
+    } else if response.starts_with("Nothing changed")
+        || response == "Player is already whitelisted"
+        || response == "Player is not whitelisted"
+    {
+        Ok(PlayerChange::Unchanged)
+    } else if response == "That player does not exist" || response == "No player was found" {
+        Ok(PlayerChange::NotFound)
+    } else {
+        Err(unexpected(response))
+    }
+}
+
+fn split_names(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub struct List;
+
+impl Command for List {
+    type Output = PlayerList;
+
+    fn to_command_line(&self) -> String {
+        "list".to_string()
+    }
+
+    /// Parses "There are 1 of a max of 20 players online: foo" as well as
+    /// "There are 1/20 players online:foo" sent by servers before 1.13.
+    fn parse_response(&self, response: &str) -> Result<PlayerList> {
+        let (summary, names) = response
+            .split_once(':')
+            .ok_or_else(|| unexpected(response))?;
+
+        let counts = summary
+            .strip_prefix("There are ")
+            .and_then(|s| s.strip_suffix(" players online"))
+            .ok_or_else(|| unexpected(response))?;
+        let (online, max) = counts
+            .split_once(" of a max of ")
+            .or_else(|| counts.split_once('/'))
+            .ok_or_else(|| unexpected(response))?;
+
+        Ok(PlayerList {
+            online: online.parse().map_err(|_| unexpected(response))?,
+            max: max.parse().map_err(|_| unexpected(response))?,
+            players: split_names(names),
+        })
+    }
+}
+
+pub struct WhitelistAdd(pub PlayerName);
+
+impl Command for WhitelistAdd {
+    type Output = PlayerChange;
+
+    fn to_command_line(&self) -> String {
+        format!("whitelist add {}", self.0)
+    }
+
+    fn parse_response(&self, response: &str) -> Result<PlayerChange> {
+        parse_player_change(response, "Added ")
+    }
+}
+
+pub struct WhitelistRemove(pub PlayerName);
+
+impl Command for WhitelistRemove {
+    type Output = PlayerChange;
+
+    fn to_command_line(&self) -> String {
+        format!("whitelist remove {}", self.0)
+    }
+
+    fn parse_response(&self, response: &str) -> Result<PlayerChange> {
+        parse_player_change(response, "Removed ")
+    }
+}
+
+pub struct WhitelistList;
+
+impl Command for WhitelistList {
+    type Output = Vec<String>;
+
+    fn to_command_line(&self) -> String {
+        "whitelist list".to_string()
+    }
+
+    fn parse_response(&self, response: &str) -> Result<Vec<String>> {
+        if response.starts_with("There are no whitelisted players") {
+            return Ok(Vec::new());
+        }
+
+        match response.split_once(':') {
+            Some((summary, names)) if summary.starts_with("There are ") => Ok(split_names(names)),
+            _ => Err(unexpected(response)),
+        }
+    }
+}
+
+pub struct Op(pub PlayerName);
+
+impl Command for Op {
+    type Output = PlayerChange;
+
+    fn to_command_line(&self) -> String {
+        format!("op {}", self.0)
+    }
+
+    fn parse_response(&self, response: &str) -> Result<PlayerChange> {
+        parse_player_change(response, "Made ")
+    }
+}
+
+pub struct Deop(pub PlayerName);
+
+impl Command for Deop {
+    type Output = PlayerChange;
+
+    fn to_command_line(&self) -> String {
+        format!("deop {}", self.0)
+    }
+
+    fn parse_response(&self, response: &str) -> Result<PlayerChange> {
+        parse_player_change(response, "Made ")
+    }
+}
+
+fn with_reason(cmd: String, reason: &Option<Message>) -> String {
+    match reason {
+        Some(reason) => format!("{} {}", cmd, reason),
+        None => cmd,
+    }
+}
+
+pub struct Kick {
+    pub player: PlayerName,
+    pub reason: Option<Message>,
+}
+
+impl Command for Kick {
+    type Output = PlayerChange;
+
+    fn to_command_line(&self) -> String {
+        with_reason(format!("kick {}", self.player), &self.reason)
+    }
+
+    fn parse_response(&self, response: &str) -> Result<PlayerChange> {
+        parse_player_change(response, "Kicked ")
+    }
+}
+
+pub struct Ban {
+    pub player: PlayerName,
+    pub reason: Option<Message>,
+}
+
+impl Command for Ban {
+    type Output = PlayerChange;
+
+    fn to_command_line(&self) -> String {
+        with_reason(format!("ban {}", self.player), &self.reason)
+    }
+
+    fn parse_response(&self, response: &str) -> Result<PlayerChange> {
+        parse_player_change(response, "Banned ")
+    }
+}
+
+pub struct SaveAll {
+    pub flush: bool,
+}
+
+impl Command for SaveAll {
+    type Output = String;
+
+    fn to_command_line(&self) -> String {
+        if self.flush {
+            "save-all flush".to_string()
+        } else {
+            "save-all".to_string()
+        }
+    }
+
+    raw_response!();
+}
+
+pub struct SaveOff;
+
+impl Command for SaveOff {
+    type Output = String;
+
+    fn to_command_line(&self) -> String {
+        "save-off".to_string()
+    }
+
+    raw_response!();
+}
+
+pub struct SaveOn;
+
+impl Command for SaveOn {
+    type Output = String;
+
+    fn to_command_line(&self) -> String {
+        "save-on".to_string()
+    }
+
+    raw_response!();
+}
+
+pub struct Say(pub Message);
+
+impl Command for Say {
+    type Output = String;
+
+    fn to_command_line(&self) -> String {
+        format!("say {}", self.0)
+    }
+
+    raw_response!();
+}
+
+pub struct Tellraw {
+    pub target: Target,
+    /// Raw JSON text component.
+    pub message: Value,
+}
+
+impl Command for Tellraw {
+    type Output = String;
+
+    fn to_command_line(&self) -> String {
+        // Serialized in compact form, so that it never contains a line break.
+        format!("tellraw {} {}", self.target, self.message)
+    }
+
+    raw_response!();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeQuery {
+    Daytime,
+    Gametime,
+    Day,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Time {
+    Set(u32),
+    Add(u32),
+    Query(TimeQuery),
+}
+
+impl Command for Time {
+    /// Time in ticks after the command is executed.
+    type Output = u64;
+
+    fn to_command_line(&self) -> String {
+        match self {
+            Self::Set(ticks) => format!("time set {}", ticks),
+            Self::Add(ticks) => format!("time add {}", ticks),
+            Self::Query(TimeQuery::Daytime) => "time query daytime".to_string(),
+            Self::Query(TimeQuery::Gametime) => "time query gametime".to_string(),
+            Self::Query(TimeQuery::Day) => "time query day".to_string(),
+        }
+    }
+
+    /// Parses "Set the time to 1000" or "The time is 1000".
+    fn parse_response(&self, response: &str) -> Result<u64> {
+        response
+            .rsplit(' ')
+            .next()
+            .and_then(|ticks| ticks.parse().ok())
+            .ok_or_else(|| unexpected(response))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeatherKind {
+    Clear,
+    Rain,
+    Thunder,
+}
+
+pub struct Weather {
+    pub kind: WeatherKind,
+    /// Duration in seconds.
+    pub duration: Option<u32>,
+}
+
+impl Command for Weather {
+    /// Weather after the command is executed.
+    type Output = WeatherKind;
+
+    fn to_command_line(&self) -> String {
+        let kind = match self.kind {
+            WeatherKind::Clear => "clear",
+            WeatherKind::Rain => "rain",
+            WeatherKind::Thunder => "thunder",
+        };
+        match self.duration {
+            Some(duration) => format!("weather {} {}", kind, duration),
+            None => format!("weather {}", kind),
+        }
+    }
+
+    /// Parses "Set the weather to rain & thunder", or "Changing to rain and
+    /// thunder" sent by servers before 1.13.
+    fn parse_response(&self, response: &str) -> Result<WeatherKind> {
+        let kind = response
+            .strip_prefix("Set the weather to ")
+            .or_else(|| response.strip_prefix("Changing to "))
+            .ok_or_else(|| unexpected(response))?;
+        match kind.trim_end_matches(" weather") {
+            "clear" => Ok(WeatherKind::Clear),
+            "rain" => Ok(WeatherKind::Rain),
+            "rain & thunder" | "rain and thunder" => Ok(WeatherKind::Thunder),
+            _ => Err(unexpected(response)),
+        }
+    }
+}
+
+/// Queries a game rule, or sets it if a value is given.
+pub struct GameRule {
+    pub rule: GameRuleName,
+    pub value: Option<GameRuleValue>,
+}
+
+impl Command for GameRule {
+    /// Value of the game rule after the command is executed.
+    type Output = String;
+
+    fn to_command_line(&self) -> String {
+        match self.value {
+            Some(value) => format!("gamerule {} {}", self.rule.0, value),
+            None => format!("gamerule {}", self.rule.0),
+        }
+    }
+
+    /// Parses "Gamerule keepInventory is currently set to: false" or
+    /// "Gamerule keepInventory is now set to: true".
+    fn parse_response(&self, response: &str) -> Result<String> {
+        match response.rsplit_once(": ") {
+            Some((summary, value)) if summary.starts_with("Gamerule ") => Ok(value.to_string()),
+            _ => Err(unexpected(response)),
+        }
+    }
+}
+
+pub struct Stop;
+
+impl Command for Stop {
+    type Output = String;
+
+    fn to_command_line(&self) -> String {
+        "stop".to_string()
+    }
+
+    raw_response!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_name() {
+        assert!(PlayerName::new("hoge_Fuga123").is_ok());
+        assert!(PlayerName::new("").is_err());
+        assert!(PlayerName::new("a_very_long_player_name").is_err());
+        assert!(PlayerName::new("hoge stop").is_err());
+        assert!(Message::new("hello\nstop").is_err());
+        assert!(Message::new(&"a".repeat(MAX_MESSAGE_LEN)).is_ok());
+        assert!(Message::new(&"a".repeat(MAX_MESSAGE_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_command_line() {
+        let player = PlayerName::new("hoge").unwrap();
+        assert_eq!("op hoge", Op(player.clone()).to_command_line());
+        assert_eq!(
+            "kick hoge Too much lag",
+            Kick {
+                player: player.clone(),
+                reason: Some(Message::new("Too much lag").unwrap()),
+            }
+            .to_command_line()
+        );
+        assert_eq!(
+            r#"tellraw @a {"text":"a\nb"}"#,
+            Tellraw {
+                target: Target::AllPlayers,
+                message: serde_json::json!({"text": "a\nb"}),
+            }
+            .to_command_line()
+        );
+        assert_eq!(
+            "gamerule keepInventory true",
+            GameRule {
+                rule: GameRuleName::new("keepInventory").unwrap(),
+                value: Some(GameRuleValue::Bool(true)),
+            }
+            .to_command_line()
+        );
+    }
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(
+            PlayerList {
+                online: 2,
+                max: 20,
+                players: vec!["foo".to_string(), "bar".to_string()],
+            },
+            List.parse_response("There are 2 of a max of 20 players online: foo, bar")
+                .unwrap()
+        );
+        assert_eq!(
+            PlayerList {
+                online: 0,
+                max: 10,
+                players: vec![],
+            },
+            List.parse_response("There are 0 of a max of 10 players online: ")
+                .unwrap()
+        );
+        assert_eq!(
+            PlayerList {
+                online: 1,
+                max: 20,
+                players: vec!["foo".to_string()],
+            },
+            List.parse_response("There are 1/20 players online:foo")
+                .unwrap()
+        );
+        assert!(List.parse_response("Unknown command").is_err());
+    }
+
+    #[test]
+    fn test_parse_whitelist_list() {
+        assert_eq!(
+            vec!["foo", "bar"],
+            WhitelistList
+                .parse_response("There are 2 whitelisted player(s): foo, bar")
+                .unwrap()
+        );
+        assert!(WhitelistList
+            .parse_response("There are no whitelisted players")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_parse_player_change() {
+        let player = PlayerName::new("foo").unwrap();
+        assert_eq!(
+            PlayerChange::Changed,
+            WhitelistAdd(player.clone())
+                .parse_response("Added foo to the whitelist")
+                .unwrap()
+        );
+        assert_eq!(
+            PlayerChange::Unchanged,
+            WhitelistRemove(player.clone())
+                .parse_response("Player is not whitelisted")
+                .unwrap()
+        );
+        assert_eq!(
+            PlayerChange::Unchanged,
+            Op(player.clone())
+                .parse_response("Nothing changed. The player already is an operator")
+                .unwrap()
+        );
+        assert_eq!(
+            PlayerChange::NotFound,
+            Kick {
+                player: player.clone(),
+                reason: None,
+            }
+            .parse_response("No player was found")
+            .unwrap()
+        );
+        assert!(Deop(player).parse_response("Unknown command").is_err());
+    }
+
+    #[test]
+    fn test_parse_weather() {
+        let weather = Weather {
+            kind: WeatherKind::Thunder,
+            duration: None,
+        };
+        assert_eq!(
+            WeatherKind::Thunder,
+            weather
+                .parse_response("Set the weather to rain & thunder")
+                .unwrap()
+        );
+        assert_eq!(
+            WeatherKind::Clear,
+            weather.parse_response("Changing to clear weather").unwrap()
+        );
+        assert!(weather.parse_response("Unknown command").is_err());
+    }
+
+    #[test]
+    fn test_parse_time_and_gamerule() {
+        assert_eq!(
+            1000,
+            Time::Set(1000)
+                .parse_response("Set the time to 1000")
+                .unwrap()
+        );
+        assert_eq!(
+            "false",
+            GameRule {
+                rule: GameRuleName::new("keepInventory").unwrap(),
+                value: None,
+            }
+            .parse_response("Gamerule keepInventory is currently set to: false")
+            .unwrap()
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use log::warn;
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+/// A line of the server log in the form of `[time] [thread/LEVEL]: message`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    pub time: String,
+    pub thread: String,
+    pub level: Level,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogEvent {
+    /// Progress of preparing the spawn area in percent.
+    Starting(u8),
+    Done(Duration),
+    PlayerJoined(String),
+    PlayerLeft(String),
+    Chat {
+        player: String,
+        message: String,
+    },
+    Death {
+        player: String,
+        message: String,
+    },
+    CantKeepUp {
+        behind: Duration,
+        ticks: u32,
+    },
+    Stopping,
+}
+
+const DEATH_MESSAGES: &[&str] = &[
+    " was slain by ",
+    " was shot by ",
+    " was killed",
+    " was blown up by ",
+    " was fireballed by ",
+    " was pummeled by ",
+    " was impaled by ",
+    " was pricked to death",
+    " was squashed by ",
+    " was squished ",
+    " was struck by lightning",
+    " was stung to death",
+    " was poked to death",
+    " was skewered by ",
+    " was obliterated by ",
+    " was doomed to fall",
+    " was roasted in dragon's breath",
+    " was frozen to death",
+    " drowned",
+    " died",
+    " blew up",
+    " burned to death",
+    " went up in flames",
+    " walked into fire",
+    " walked into the danger zone",
+    " discovered the floor was lava",
+    " tried to swim in lava",
+    " hit the ground too hard",
+    " fell from a high place",
+    " fell off ",
+    " fell out of the world",
+    " fell too far",
+    " left the confines of this world",
+    " suffocated in a wall",
+    " was squeezed too much",
+    " starved to death",
+    " froze to death",
+    " withered away",
+    " experienced kinetic energy",
+    " didn't want to live in the same world as ",
+];
+
+fn parse_level(level: &str) -> Option<Level> {
+    match level {
+        "DEBUG" => Some(Level::Debug),
+        "INFO" => Some(Level::Info),
+        "WARN" => Some(Level::Warn),
+        "ERROR" => Some(Level::Error),
+        "FATAL" => Some(Level::Fatal),
+        _ => None,
+    }
+}
+
+pub fn parse_line(line: &str) -> Option<LogLine> {
+    let rest = line.strip_prefix('[')?;
+    let (time, rest) = rest.split_once("] [")?;
+    let (source, message) = rest.split_once("]: ")?;
+    let (thread, level) = source.rsplit_once('/')?;
+
+    Some(LogLine {
+        time: time.to_string(),
+        thread: thread.to_string(),
+        level: parse_level(level)?,
+        message: message.to_string(),
+    })
+}
+
+fn is_player_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 16
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+fn parse_seconds(seconds: &str) -> Option<Duration> {
+    seconds
+        .parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+}
+
+/// Parses "Running 2001ms or 40 ticks behind".
+fn parse_cant_keep_up(message: &str) -> Option<LogEvent> {
+    let rest = message.split_once("Running ")?.1;
+    let (behind, rest) = rest.split_once("ms or ")?;
+    let ticks = rest.strip_suffix(" ticks behind")?;
+
+    Some(LogEvent::CantKeepUp {
+        behind: Duration::from_millis(behind.parse().ok()?),
+        ticks: ticks.parse().ok()?,
+    })
+}
+
+fn parse_player_message(message: &str) -> Option<LogEvent> {
+    if let Some(rest) = message.strip_prefix('<') {
+        let (player, message) = rest.split_once("> ")?;
+        return is_player_name(player).then(|| LogEvent::Chat {
+            player: player.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    let (player, rest) = message.split_once(' ')?;
+    if !is_player_name(player) {
+        return None;
+    }
+
+    match rest {
+        "joined the game" => Some(LogEvent::PlayerJoined(player.to_string())),
+        "left the game" => Some(LogEvent::PlayerLeft(player.to_string())),
+        _ => {
+            let rest = &message[player.len()..];
+            DEATH_MESSAGES
+                .iter()
+                .any(|death| rest.starts_with(death))
+                .then(|| LogEvent::Death {
+                    player: player.to_string(),
+                    message: message.to_string(),
+                })
+        }
+    }
+}
+
+pub fn parse_event(line: &LogLine) -> Option<LogEvent> {
+    let message = line.message.as_str();
+
+    if let Some(progress) = message
+        .strip_prefix("Preparing spawn area: ")
+        .and_then(|s| s.strip_suffix('%'))
+    {
+        return progress.parse().ok().map(LogEvent::Starting);
+    }
+    if let Some(rest) = message.strip_prefix("Done (") {
+        let (elapsed, _) = rest.split_once("s)!")?;
+        return parse_seconds(elapsed).map(LogEvent::Done);
+    }
+    if message.starts_with("Can't keep up!") {
+        return parse_cant_keep_up(message);
+    }
+    if message == "Stopping the server" {
+        return Some(LogEvent::Stopping);
+    }
+
+    // Only messages from the server thread can be about players, so that
+    // other threads cannot forge them.
+    if line.thread == "Server thread" && line.level == Level::Info {
+        return parse_player_message(message);
+    }
+
+    None
+}
+
+/// Copies the output of the server line by line, passing recognized events to
+/// `on_event` until the output is closed.
+///
+/// Failing to echo does not stop reading, since the server blocks once the
+/// pipe of its output is full.
+pub fn forward_output<R, W, F>(output: R, mut echo: W, mut on_event: F) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(LogEvent),
+{
+    let mut reader = BufReader::new(output);
+    let mut buf = Vec::new();
+    let mut echo_failed = false;
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
+        }
+        if let Err(err) = echo.write_all(&buf).and_then(|_| echo.flush()) {
+            if !echo_failed {
+                warn!("Failed to echo the output of the server: {}", err);
+                echo_failed = true;
+            }
+        }
+
+        let line = String::from_utf8_lossy(&buf);
+        if let Some(event) = parse_line(line.trim_end()).as_ref().and_then(parse_event) {
+            on_event(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Option<LogEvent> {
+        parse_event(&parse_line(line).unwrap())
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            Some(LogLine {
+                time: "12:00:00".to_string(),
+                thread: "Worker-Main-2".to_string(),
+                level: Level::Info,
+                message: "Preparing spawn area: 42%".to_string(),
+            }),
+            parse_line("[12:00:00] [Worker-Main-2/INFO]: Preparing spawn area: 42%")
+        );
+        assert_eq!(
+            "RCON Client /127.0.0.1 #1",
+            parse_line("[12:00:00] [RCON Client /127.0.0.1 #1/INFO]: Thread RCON Client")
+                .unwrap()
+                .thread
+        );
+        assert_eq!(None, parse_line("Starting net.minecraft.server.Main"));
+        assert_eq!(
+            None,
+            parse_line("\tat java.lang.Thread.run(Thread.java:1623)")
+        );
+    }
+
+    #[test]
+    fn test_parse_event() {
+        assert_eq!(
+            Some(LogEvent::Starting(42)),
+            parse("[12:00:00] [Worker-Main-2/INFO]: Preparing spawn area: 42%")
+        );
+        assert_eq!(
+            Some(LogEvent::Done(Duration::from_millis(16760))),
+            parse(r#"[12:00:00] [Server thread/INFO]: Done (16.760s)! For help, type "help""#)
+        );
+        assert_eq!(
+            Some(LogEvent::PlayerJoined("foo".to_string())),
+            parse("[12:00:00] [Server thread/INFO]: foo joined the game")
+        );
+        assert_eq!(
+            Some(LogEvent::PlayerLeft("foo".to_string())),
+            parse("[12:00:00] [Server thread/INFO]: foo left the game")
+        );
+        assert_eq!(
+            Some(LogEvent::Chat {
+                player: "foo".to_string(),
+                message: "hello world".to_string(),
+            }),
+            parse("[12:00:00] [Server thread/INFO]: <foo> hello world")
+        );
+        assert_eq!(
+            Some(LogEvent::Death {
+                player: "foo".to_string(),
+                message: "foo was slain by Zombie".to_string(),
+            }),
+            parse("[12:00:00] [Server thread/INFO]: foo was slain by Zombie")
+        );
+        assert_eq!(
+            Some(LogEvent::CantKeepUp {
+                behind: Duration::from_millis(2001),
+                ticks: 40,
+            }),
+            parse("[12:00:00] [Server thread/WARN]: Can't keep up! Is the server overloaded? Running 2001ms or 40 ticks behind")
+        );
+        assert_eq!(
+            Some(LogEvent::Stopping),
+            parse("[12:00:00] [Server thread/INFO]: Stopping the server")
+        );
+        assert_eq!(
+            None,
+            parse("[12:00:00] [User Authenticator #1/INFO]: foo joined the game")
+        );
+        assert_eq!(
+            None,
+            parse("[12:00:00] [Server thread/INFO]: Saving chunks for level 'ServerLevel[world]'/minecraft:overworld")
+        );
+    }
+
+    #[test]
+    fn test_forward_output() {
+        let output = "Starting net.minecraft.server.Main\n\
+                      [12:00:00] [Server thread/INFO]: foo joined the game\n\
+                      [12:00:01] [Server thread/INFO]: Stopping the server";
+        let mut echo = Vec::new();
+        let mut events = Vec::new();
+
+        forward_output(output.as_bytes(), &mut echo, |event| events.push(event)).unwrap();
+
+        assert_eq!(output.as_bytes(), echo.as_slice());
+        assert_eq!(
+            vec![
+                LogEvent::PlayerJoined("foo".to_string()),
+                LogEvent::Stopping
+            ],
+            events
+        );
+    }
+
+    struct BrokenPipe;
+
+    impl Write for BrokenPipe {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_forward_output_echo_failure() {
+        let output = "[12:00:00] [Server thread/INFO]: foo joined the game\n\
+                      [12:00:01] [Server thread/INFO]: foo left the game\n";
+        let mut events = Vec::new();
+
+        forward_output(output.as_bytes(), BrokenPipe, |event| events.push(event)).unwrap();
+
+        assert_eq!(
+            vec![
+                LogEvent::PlayerJoined("foo".to_string()),
+                LogEvent::PlayerLeft("foo".to_string())
+            ],
+            events
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use log::{debug, info, warn};
+use premises_config::v1::{self, ServerVersion};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::fmt;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MANIFEST_PATH: &str = "/mc/game/version_manifest_v2.json";
+
+#[derive(Debug, Deserialize)]
+struct VersionManifest {
+    latest: Latest,
+    versions: Vec<VersionEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Latest {
+    release: String,
+    snapshot: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionEntry {
+    id: String,
+    url: String,
+}
+
+/// Metadata of a version. Only the parts used here are kept, and cached next
+/// to the jar.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct VersionInfo {
+    /// "release" or "snapshot" for current versions. Missing in jars cached
+    /// before it was kept.
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    /// ISO 8601 time, which sorts in order of release.
+    release_time: Option<String>,
+    downloads: Downloads,
+    /// Missing in versions from before Java 8 was required.
+    java_version: Option<JavaVersion>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Downloads {
+    /// Missing in very old versions.
+    server: Option<DownloadInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JavaVersion {
+    major_version: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DownloadInfo {
+    sha1: String,
+    size: u64,
+    url: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Http(Box<ureq::Error>),
+    Io(io::Error),
+    Json(serde_json::Error),
+    UnknownVersion(String),
+    /// The version has no server to download.
+    NoServer(String),
+    ChecksumMismatch(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(err) => write!(f, "{}", err),
+            Self::Io(err) => write!(f, "{}", err),
+            Self::Json(err) => write!(f, "Invalid response: {}", err),
+            Self::UnknownVersion(id) => write!(f, "Unknown version: {}", id),
+            Self::NoServer(id) => write!(f, "No server is available for version {}", id),
+            Self::ChecksumMismatch(url) => write!(f, "Checksum mismatch in {}", url),
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(err: ureq::Error) -> Self {
+        Self::Http(Box::new(err))
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Server jar in the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerJar {
+    pub id: String,
+    pub path: PathBuf,
+    /// Major version of Java the server needs.
+    pub java_version: Option<u32>,
+}
+
+/// Downloads server jars listed in the version manifest, keeping them by
+/// version in a cache directory.
+pub struct Downloader {
+    base_url: String,
+    cache_dir: PathBuf,
+    agent: ureq::Agent,
+}
+
+impl Downloader {
+    pub fn new(config: &v1::Download, work_dir: &Path) -> Self {
+        Self {
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            cache_dir: work_dir.join(&config.cache_dir),
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(10))
+                .timeout_read(Duration::from_secs(30))
+                .build(),
+        }
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        debug!("GET {}", url);
+        let response = self.agent.get(url).call()?;
+        Ok(serde_json::from_reader(response.into_reader())?)
+    }
+
+    fn manifest(&self) -> Result<VersionManifest, Error> {
+        self.get_json(&format!("{}{}", self.base_url, MANIFEST_PATH))
+    }
+
+    /// Reads a jar from the cache, which has the metadata of its version
+    /// next to it.
+    fn read_cached(&self, id: &str) -> Option<(ServerJar, VersionInfo)> {
+        let path = self.cache_dir.join(format!("minecraft_server.{}.jar", id));
+        let info = fs::read(path.with_extension("json")).ok()?;
+        let info: VersionInfo = serde_json::from_slice(&info).ok()?;
+        let jar = ServerJar {
+            id: id.to_string(),
+            java_version: info.java_version.as_ref().map(|java| java.major_version),
+            path,
+        };
+        jar.path.is_file().then_some((jar, info))
+    }
+
+    fn cached_jar(&self, id: &str) -> Option<ServerJar> {
+        self.read_cached(id).map(|(jar, _)| jar)
+    }
+
+    /// Looks up the Java version a jar given in the config needs, in the
+    /// metadata cached for its version.
+    pub fn java_version(&self, jar: &Path, version: &ServerVersion) -> Option<u32> {
+        let id = match version {
+            ServerVersion::Id(id) => id.as_str(),
+            // Jars in the cache are named after their version.
+            _ => jar
+                .file_name()?
+                .to_str()?
+                .strip_prefix("minecraft_server.")?
+                .strip_suffix(".jar")?,
+        };
+        self.cached_jar(id)?.java_version
+    }
+
+    /// Falls back to the newest jar in the cache whose version is of type
+    /// `kind` ("release" or "snapshot") when the latest version cannot be
+    /// looked up because of `err`.
+    fn newest_cached(&self, kind: &str, err: Error) -> Result<ServerJar, Error> {
+        let Ok(entries) = fs::read_dir(&self.cache_dir) else {
+            return Err(err);
+        };
+        let newest = entries
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                let id = name
+                    .strip_prefix("minecraft_server.")?
+                    .strip_suffix(".jar")?;
+                let (jar, info) = self.read_cached(id)?;
+                (info.kind.as_deref() == Some(kind)).then_some((info.release_time, jar))
+            })
+            .max_by(|(a, _), (b, _)| a.cmp(b));
+        match newest {
+            Some((_, jar)) => {
+                warn!(
+                    "Failed to fetch the version manifest; using cached server {}: {}",
+                    jar.id, err
+                );
+                Ok(jar)
+            }
+            None => Err(err),
+        }
+    }
+
+    /// Returns the server jar of `version`, downloading it unless it is in
+    /// the cache.
+    pub fn server_jar(&self, version: &ServerVersion) -> Result<ServerJar, Error> {
+        // A version given by id can be used without looking it up.
+        let (id, manifest) = match version {
+            ServerVersion::Id(id) => (id.clone(), None),
+            ServerVersion::LatestRelease => match self.manifest() {
+                Ok(manifest) => (manifest.latest.release.clone(), Some(manifest)),
+                Err(err @ Error::Http(_)) => return self.newest_cached("release", err),
+                Err(err) => return Err(err),
+            },
+            ServerVersion::LatestSnapshot => match self.manifest() {
+                Ok(manifest) => (manifest.latest.snapshot.clone(), Some(manifest)),
+                Err(err @ Error::Http(_)) => return self.newest_cached("snapshot", err),
+                Err(err) => return Err(err),
+            },
+        };
+        if let Some(jar) = self.cached_jar(&id) {
+            debug!("Using cached server jar {}", jar.path.display());
+            return Ok(jar);
+        }
+
+        let manifest = match manifest {
+            Some(manifest) => manifest,
+            None => self.manifest()?,
+        };
+        let entry = manifest
+            .versions
+            .iter()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| Error::UnknownVersion(id.clone()))?;
+        let info: VersionInfo = self.get_json(&entry.url)?;
+        let server = info
+            .downloads
+            .server
+            .as_ref()
+            .ok_or_else(|| Error::NoServer(id.clone()))?;
+
+        info!("Downloading Minecraft server {}", id);
+        create_dir_all(&self.cache_dir)?;
+        let path = self.cache_dir.join(format!("minecraft_server.{}.jar", id));
+        // Only verified jars get their final name, so that a cached jar can
+        // be trusted.
+        let partial_path = path.with_extension("jar.partial");
+        if let Err(err) = self.download(server, &partial_path) {
+            let _ = fs::remove_file(&partial_path);
+            return Err(err);
+        }
+        fs::write(path.with_extension("json"), serde_json::to_vec(&info)?)?;
+        fs::rename(&partial_path, &path)?;
+        Ok(ServerJar {
+            id,
+            path,
+            java_version: info.java_version.map(|java| java.major_version),
+        })
+    }
+
+    fn download(&self, info: &DownloadInfo, path: &Path) -> Result<(), Error> {
+        debug!("GET {}", info.url);
+        let mut reader = self.agent.get(&info.url).call()?.into_reader();
+        let mut file = File::create(path)?;
+        let mut hasher = Sha1::new();
+        let mut size = 0;
+        let mut buf = [0; 64 * 1024];
+        loop {
+            let len = reader.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            hasher.update(&buf[..len]);
+            file.write_all(&buf[..len])?;
+            size += len as u64;
+        }
+        file.sync_all()?;
+
+        if size != info.size || format!("{:x}", hasher.finalize()) != info.sha1 {
+            return Err(Error::ChecksumMismatch(info.url.clone()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use tiny_http::{Response, Server};
+
+    const JAR: &[u8] = b"server jar";
+
+    /// Serves a version manifest with a release and a snapshot, counting
+    /// the requests for jars.
+    fn serve(jar_sha1: String) -> (String, Arc<AtomicUsize>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", server.server_addr());
+        let downloads = Arc::new(AtomicUsize::new(0));
+
+        let url = base_url.clone();
+        let count = downloads.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let body = match request.url() {
+                    MANIFEST_PATH => json!({
+                        "latest": {"release": "1.20.1", "snapshot": "23w31a"},
+                        "versions": [
+                            {"id": "23w31a", "url": format!("{}/v1/packages/23w31a.json", url)},
+                            {"id": "1.20.1", "url": format!("{}/v1/packages/1.20.1.json", url)},
+                        ],
+                    })
+                    .to_string()
+                    .into_bytes(),
+                    "/v1/packages/1.20.1.json" => json!({
+                        "downloads": {"server": {
+                            "sha1": jar_sha1,
+                            "size": JAR.len(),
+                            "url": format!("{}/v1/objects/server.jar", url),
+                        }},
+                        "javaVersion": {"component": "java-runtime-gamma", "majorVersion": 17},
+                        "releaseTime": "2023-06-12T13:25:51+00:00",
+                        "type": "release",
+                    })
+                    .to_string()
+                    .into_bytes(),
+                    "/v1/packages/23w31a.json" => json!({"downloads": {}}).to_string().into_bytes(),
+                    "/v1/objects/server.jar" => {
+                        count.fetch_add(1, Ordering::SeqCst);
+                        JAR.to_vec()
+                    }
+                    _ => {
+                        request.respond(Response::empty(404)).unwrap();
+                        continue;
+                    }
+                };
+                request.respond(Response::from_data(body)).unwrap();
+            }
+        });
+        (base_url, downloads)
+    }
+
+    fn downloader(base_url: String, name: &str) -> (Downloader, PathBuf) {
+        let work_dir = std::env::temp_dir().join(format!("guardian-{}-{}", name, process::id()));
+        let config = v1::Download {
+            base_url,
+            cache_dir: PathBuf::from("jars"),
+        };
+        (Downloader::new(&config, &work_dir), work_dir)
+    }
+
+    #[test]
+    fn test_server_jar() {
+        let (base_url, downloads) = serve(format!("{:x}", Sha1::digest(JAR)));
+        let (downloader, work_dir) = downloader(base_url, "download");
+
+        let jar = downloader
+            .server_jar(&ServerVersion::LatestRelease)
+            .unwrap();
+        assert_eq!(work_dir.join("jars/minecraft_server.1.20.1.jar"), jar.path);
+        assert_eq!(Some(17), jar.java_version);
+        assert_eq!(JAR, fs::read(&jar.path).unwrap());
+        let cached = downloader
+            .server_jar(&ServerVersion::Id("1.20.1".to_string()))
+            .unwrap();
+        assert_eq!(jar, cached);
+        assert_eq!(1, downloads.load(Ordering::SeqCst));
+
+        let given = Path::new("/srv/server.jar");
+        let id = ServerVersion::Id("1.20.1".to_string());
+        let latest = ServerVersion::LatestRelease;
+        assert_eq!(Some(17), downloader.java_version(given, &id));
+        assert_eq!(Some(17), downloader.java_version(&jar.path, &latest));
+        assert_eq!(None, downloader.java_version(given, &latest));
+
+        let snapshot = downloader.server_jar(&ServerVersion::LatestSnapshot);
+        let unknown = downloader.server_jar(&ServerVersion::Id("1.0".to_string()));
+        fs::remove_dir_all(&work_dir).unwrap();
+        assert!(matches!(snapshot, Err(Error::NoServer(id)) if id == "23w31a"));
+        assert!(matches!(unknown, Err(Error::UnknownVersion(id)) if id == "1.0"));
+    }
+
+    #[test]
+    fn test_offline_fallback() {
+        let (base_url, _) = serve(format!("{:x}", Sha1::digest(JAR)));
+        let (online, work_dir) = downloader(base_url, "offline");
+        let jar = online.server_jar(&ServerVersion::LatestRelease).unwrap();
+
+        // Nothing listens on port 1.
+        let (offline, _) = downloader("http://127.0.0.1:1".to_string(), "offline");
+        let release = offline.server_jar(&ServerVersion::LatestRelease);
+        let snapshot = offline.server_jar(&ServerVersion::LatestSnapshot);
+        fs::remove_dir_all(&work_dir).unwrap();
+        assert_eq!(jar, release.unwrap());
+        assert!(matches!(snapshot, Err(Error::Http(_))));
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let (base_url, _) = serve(format!("{:x}", Sha1::digest(b"other jar")));
+        let (downloader, work_dir) = downloader(base_url, "mismatch");
+
+        let result = downloader.server_jar(&ServerVersion::LatestRelease);
+        let cached = fs::read_dir(work_dir.join("jars")).unwrap().count();
+        fs::remove_dir_all(&work_dir).unwrap();
+        assert!(matches!(result, Err(Error::ChecksumMismatch(_))));
+        assert_eq!(0, cached);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::lifecycle::State;
+use crate::metrics::Metrics;
+use crate::monitor::{Event, EventKind, Health};
+use guardian::console::LogEvent;
+use guardian::slp;
+use std::collections::VecDeque;
+
+/// Recent events and the latest status derived from them.
+#[derive(Debug)]
+pub struct History {
+    capacity: usize,
+    next_id: u64,
+    events: VecDeque<(u64, Event)>,
+    pub state: Option<State>,
+    pub health: Option<Health>,
+    pub status: Option<slp::Status>,
+    /// Number of players online, from the status and the players joining and
+    /// leaving since then.
+    pub players: Option<u32>,
+    /// Latest metrics while the server is running.
+    pub metrics: Option<Metrics>,
+    /// Version of the server last seen in its status, kept while it is not
+    /// running.
+    pub version: Option<String>,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            next_id: 1,
+            events: VecDeque::with_capacity(capacity),
+            state: None,
+            health: None,
+            status: None,
+            players: None,
+            metrics: None,
+            version: None,
+        }
+    }
+
+    pub fn push(&mut self, event: Event) {
+        match &event.kind {
+            EventKind::StateChanged(state) => {
+                self.state = Some(*state);
+                if *state != State::Online {
+                    self.status = None;
+                    self.players = None;
+                }
+                if !matches!(state, State::Starting(_) | State::Online | State::Stopping) {
+                    self.metrics = None;
+                }
+            }
+            EventKind::HealthChanged(health) => self.health = Some(*health),
+            EventKind::Metrics(metrics) => self.metrics = Some(metrics.clone()),
+            EventKind::Console(LogEvent::PlayerJoined(_)) => {
+                self.players = self.players.map(|players| players + 1);
+            }
+            EventKind::Console(LogEvent::PlayerLeft(_)) => {
+                self.players = self.players.map(|players| players.saturating_sub(1));
+            }
+            EventKind::Status(status) => {
+                self.players = Some(status.players_online);
+                self.status = Some(status.clone());
+                self.version = Some(status.version.clone());
+            }
+            _ => (),
+        }
+
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back((self.next_id, event));
+        self.next_id += 1;
+    }
+
+    /// Events with an id greater than `id`, oldest first.
+    pub fn since(&self, id: u64) -> impl Iterator<Item = &(u64, Event)> {
+        self.events
+            .iter()
+            .filter(move |(event_id, _)| *event_id > id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_history() {
+        let mut history = History::new(2);
+        history.push(Event::new(EventKind::StateChanged(State::Preparing)));
+        history.push(Event::new(EventKind::HealthChanged(Health::Unreachable)));
+        history.push(Event::new(EventKind::StateChanged(State::Online)));
+
+        assert_eq!(Some(State::Online), history.state);
+        assert_eq!(Some(Health::Unreachable), history.health);
+        let ids: Vec<_> = history.since(0).map(|(id, _)| *id).collect();
+        assert_eq!(vec![2, 3], ids);
+        let ids: Vec<_> = history.since(2).map(|(id, _)| *id).collect();
+        assert_eq!(vec![3], ids);
+    }
+
+    #[test]
+    fn test_players() {
+        let mut history = History::new(10);
+        history.push(Event::new(EventKind::StateChanged(State::Online)));
+        history.push(Event::new(EventKind::Console(LogEvent::PlayerJoined(
+            "Steve".to_string(),
+        ))));
+        assert_eq!(None, history.players);
+
+        history.push(Event::new(EventKind::Status(slp::Status {
+            version: "1.20.1".to_string(),
+            protocol: 763,
+            motd: "A Minecraft Server".to_string(),
+            players_online: 1,
+            players_max: 20,
+            players: vec!["Steve".to_string()],
+            latency: Duration::from_millis(1),
+        })));
+        history.push(Event::new(EventKind::Console(LogEvent::PlayerJoined(
+            "Alex".to_string(),
+        ))));
+        assert_eq!(Some(2), history.players);
+        for _ in 0..3 {
+            history.push(Event::new(EventKind::Console(LogEvent::PlayerLeft(
+                "Alex".to_string(),
+            ))));
+        }
+        assert_eq!(Some(0), history.players);
+
+        history.push(Event::new(EventKind::StateChanged(State::Stopping)));
+        assert_eq!(None, history.players);
+    }
+}
@@ -0,0 +1,79 @@
+use crate::history::History;
+use crate::lifecycle::State;
+use crate::supervisor::Control;
+use crossbeam_channel::{self as channel, Sender};
+use log::info;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Tracks how long the server has been online without players.
+#[derive(Debug)]
+struct IdleTracker {
+    timeout: Duration,
+    /// Since when nobody has been online.
+    since: Option<Instant>,
+}
+
+impl IdleTracker {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            since: None,
+        }
+    }
+
+    /// Records whether the server is online without players at `now`,
+    /// returning whether it has been so for the timeout. The time is then
+    /// counted anew.
+    fn update(&mut self, empty: bool, now: Instant) -> bool {
+        if !empty {
+            self.since = None;
+            return false;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if now.duration_since(since) < self.timeout {
+            return false;
+        }
+        self.since = None;
+        true
+    }
+}
+
+/// Asks the supervisor to stop the server when no players have been online
+/// for `timeout`. Servers whose player count is unknown are not stopped.
+pub fn watch_idle(timeout: Duration, history: Arc<Mutex<History>>, control_tx: Sender<Control>) {
+    let mut tracker = IdleTracker::new(timeout);
+    for now in channel::tick(CHECK_INTERVAL) {
+        let empty = {
+            let history = history.lock().unwrap();
+            history.state == Some(State::Online) && history.players == Some(0)
+        };
+        if tracker.update(empty, now) {
+            info!("No players for {} s", timeout.as_secs());
+            if control_tx.send(Control::IdleStop).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_tracker() {
+        let mut tracker = IdleTracker::new(Duration::from_secs(60));
+        let now = Instant::now();
+
+        assert!(!tracker.update(true, now));
+        assert!(!tracker.update(true, now + Duration::from_secs(59)));
+        assert!(!tracker.update(false, now + Duration::from_secs(60)));
+        assert!(!tracker.update(true, now + Duration::from_secs(61)));
+        assert!(tracker.update(true, now + Duration::from_secs(121)));
+        assert!(!tracker.update(true, now + Duration::from_secs(122)));
+    }
+}
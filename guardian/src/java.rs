@@ -0,0 +1,174 @@
+use log::{debug, warn};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Java runtime found on the system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Runtime {
+    pub path: PathBuf,
+    /// Version as reported by `java -version`, such as "17.0.8" or
+    /// "1.8.0_382".
+    pub version: String,
+    pub major: u32,
+}
+
+/// Takes the version from the output of `java -version`, which looks like
+/// `openjdk version "17.0.8" 2023-07-18`, or `java version "1.8.0_382"` for
+/// Java 8 and earlier.
+fn parse_version(output: &str) -> Option<(String, u32)> {
+    let line = output.lines().find(|line| line.contains(" version \""))?;
+    let version = line.split('"').nth(1)?;
+    let major = version.strip_prefix("1.").unwrap_or(version);
+    let major = major
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()?;
+    Some((version.to_string(), major))
+}
+
+fn probe(path: &Path) -> Option<Runtime> {
+    let output = match Command::new(path).arg("-version").output() {
+        Ok(output) => output,
+        Err(err) => {
+            debug!("Failed to run {}: {}", path.display(), err);
+            return None;
+        }
+    };
+    // The version is printed to stderr.
+    let (version, major) = parse_version(&String::from_utf8_lossy(&output.stderr))?;
+    Some(Runtime {
+        path: path.to_path_buf(),
+        version,
+        major,
+    })
+}
+
+/// Lists `java` binaries in `JAVA_HOME`, in the runtimes in `search_dirs` and
+/// in `PATH`.
+fn candidates(search_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    if let Some(java_home) = env::var_os("JAVA_HOME") {
+        candidates.push(Path::new(&java_home).join("bin/java"));
+    }
+    for dir in search_dirs {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        let mut runtimes: Vec<_> = entries
+            .filter_map(|entry| Some(entry.ok()?.path().join("bin/java")))
+            .collect();
+        runtimes.sort();
+        candidates.extend(runtimes);
+    }
+    if let Some(path) = env::var_os("PATH") {
+        candidates.extend(env::split_paths(&path).map(|dir| dir.join("java")));
+    }
+    candidates
+}
+
+/// Finds the Java runtimes on the system, skipping links to the same one.
+pub fn discover(search_dirs: &[PathBuf]) -> Vec<Runtime> {
+    let mut seen = HashSet::new();
+    candidates(search_dirs)
+        .into_iter()
+        .filter(|path| path.is_file())
+        .filter(|path| seen.insert(fs::canonicalize(path).unwrap_or_else(|_| path.clone())))
+        .filter_map(|path| probe(&path))
+        .collect()
+}
+
+/// Picks the runtime to run a server needing Java `required` with: the first
+/// one of the same major version, or else the oldest newer one if
+/// `allow_newer`. The newest is picked when the requirement is unknown.
+pub fn select(runtimes: &[Runtime], required: Option<u32>, allow_newer: bool) -> Option<&Runtime> {
+    let Some(required) = required else {
+        return runtimes.iter().max_by_key(|runtime| runtime.major);
+    };
+    if let Some(runtime) = runtimes.iter().find(|runtime| runtime.major == required) {
+        return Some(runtime);
+    }
+    if !allow_newer {
+        return None;
+    }
+
+    let runtime = runtimes
+        .iter()
+        .filter(|runtime| runtime.major > required)
+        .min_by_key(|runtime| runtime.major)?;
+    warn!(
+        "No Java {} runtime found; using Java {} as allowed by java.allow_newer",
+        required, runtime.major
+    );
+    Some(runtime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn runtime(major: u32) -> Runtime {
+        Runtime {
+            path: PathBuf::from(format!("/usr/lib/jvm/java-{}/bin/java", major)),
+            version: major.to_string(),
+            major,
+        }
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            Some(("17.0.8".to_string(), 17)),
+            parse_version(
+                "openjdk version \"17.0.8\" 2023-07-18\n\
+                 OpenJDK Runtime Environment (build 17.0.8+7-Debian-1deb12u1)\n"
+            )
+        );
+        assert_eq!(
+            Some(("1.8.0_382".to_string(), 8)),
+            parse_version("openjdk version \"1.8.0_382\"\n")
+        );
+        assert_eq!(
+            Some(("21".to_string(), 21)),
+            parse_version(
+                "Picked up JAVA_TOOL_OPTIONS: -Dfoo=bar\njava version \"21\" 2023-09-19 LTS\n"
+            )
+        );
+        assert_eq!(None, parse_version("java: command not found\n"));
+    }
+
+    // The runtimes are listed but not run, since executing a script that was
+    // just written fails with ETXTBSY while other tests fork.
+    #[test]
+    fn test_candidates() {
+        let dir = std::env::temp_dir().join(format!("guardian-java-{}", process::id()));
+        for name in ["jre-8", "jdk-21"] {
+            fs::create_dir_all(dir.join(name).join("bin")).unwrap();
+        }
+
+        let candidates = candidates(std::slice::from_ref(&dir));
+        fs::remove_dir_all(&dir).unwrap();
+        let jdk = candidates
+            .iter()
+            .position(|path| *path == dir.join("jdk-21/bin/java"));
+        let jre = candidates
+            .iter()
+            .position(|path| *path == dir.join("jre-8/bin/java"));
+        assert!(jdk.unwrap() < jre.unwrap());
+    }
+
+    #[test]
+    fn test_select() {
+        let runtimes = vec![runtime(8), runtime(21), runtime(17), runtime(17)];
+        assert_eq!(Some(&runtimes[2]), select(&runtimes, Some(17), true));
+        assert_eq!(Some(&runtimes[0]), select(&runtimes, Some(8), false));
+        assert_eq!(Some(&runtimes[1]), select(&runtimes, None, false));
+        assert_eq!(None, select(&runtimes, Some(16), false));
+        assert_eq!(Some(&runtimes[2]), select(&runtimes, Some(16), true));
+        assert_eq!(None, select(&runtimes, Some(22), true));
+    }
+}
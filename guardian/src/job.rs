@@ -0,0 +1,237 @@
+use crate::monitor::SessionError;
+use crossbeam_channel::{self as channel, select, Receiver, Sender, TrySendError};
+use guardian::command::Command;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Number of jobs that can wait in each priority.
+const QUEUE_LEN: usize = 64;
+/// Number of backups that can wait to start. A backup waiting to start
+/// covers any requested after it.
+const BACKUP_QUEUE_LEN: usize = 1;
+
+pub type JobResult = Result<String, SessionError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Jobs of the guardian itself, such as stopping the server.
+    High,
+    Normal,
+}
+
+/// Command to run on the server. The response is sent to `reply`.
+#[derive(Debug)]
+pub struct Job {
+    pub id: u64,
+    pub command: String,
+    /// How long to wait for the response, if the command may take longer
+    /// than usual.
+    pub timeout: Option<Duration>,
+    pub reply: Sender<JobResult>,
+}
+
+/// Backup of the world. The result is reported in an event.
+#[derive(Debug)]
+pub struct BackupJob {
+    pub id: u64,
+}
+
+/// Submitted job, to receive the result from.
+#[derive(Debug)]
+pub struct Ticket {
+    pub id: u64,
+    pub reply: Receiver<JobResult>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SubmitError {
+    QueueFull,
+    /// The thread running the jobs has exited.
+    Closed,
+}
+
+impl fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::QueueFull => write!(f, "Too many jobs are waiting"),
+            Self::Closed => write!(f, "Jobs are not running"),
+        }
+    }
+}
+
+/// Submits commands to the monitor, and backups to the backup worker.
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    next_id: Arc<AtomicU64>,
+    high: Sender<Job>,
+    normal: Sender<Job>,
+    backup: Sender<BackupJob>,
+}
+
+/// What the monitor should do next.
+#[derive(Debug)]
+pub enum Next {
+    HealthCheck,
+    Job(Job),
+}
+
+/// Receives jobs in order of priority.
+#[derive(Debug)]
+pub struct JobReceiver {
+    high: Receiver<Job>,
+    normal: Receiver<Job>,
+}
+
+pub fn queue() -> (JobQueue, JobReceiver, Receiver<BackupJob>) {
+    let (high_tx, high_rx) = channel::bounded(QUEUE_LEN);
+    let (normal_tx, normal_rx) = channel::bounded(QUEUE_LEN);
+    let (backup_tx, backup_rx) = channel::bounded(BACKUP_QUEUE_LEN);
+    (
+        JobQueue {
+            next_id: Arc::new(AtomicU64::new(1)),
+            high: high_tx,
+            normal: normal_tx,
+            backup: backup_tx,
+        },
+        JobReceiver {
+            high: high_rx,
+            normal: normal_rx,
+        },
+        backup_rx,
+    )
+}
+
+impl JobQueue {
+    pub fn submit(&self, command: String, priority: Priority) -> Result<Ticket, SubmitError> {
+        self.push(command, None, priority)
+    }
+
+    pub fn run<C: Command>(&self, command: &C, priority: Priority) -> Result<Ticket, SubmitError> {
+        self.push(command.to_command_line(), None, priority)
+    }
+
+    /// Runs a command which may take longer than usual to respond, waiting
+    /// for the response for `timeout`.
+    pub fn run_with_timeout<C: Command>(
+        &self,
+        command: &C,
+        timeout: Duration,
+        priority: Priority,
+    ) -> Result<Ticket, SubmitError> {
+        self.push(command.to_command_line(), Some(timeout), priority)
+    }
+
+    /// Requests a backup. `QueueFull` means that a backup is already waiting
+    /// to start.
+    pub fn backup(&self) -> Result<u64, SubmitError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        match self.backup.try_send(BackupJob { id }) {
+            Ok(_) => Ok(id),
+            Err(TrySendError::Full(_)) => Err(SubmitError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => Err(SubmitError::Closed),
+        }
+    }
+
+    fn push(
+        &self,
+        command: String,
+        timeout: Option<Duration>,
+        priority: Priority,
+    ) -> Result<Ticket, SubmitError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (reply_tx, reply_rx) = channel::bounded(1);
+        let job = Job {
+            id,
+            command,
+            timeout,
+            reply: reply_tx,
+        };
+
+        let queue = match priority {
+            Priority::High => &self.high,
+            Priority::Normal => &self.normal,
+        };
+        match queue.try_send(job) {
+            Ok(_) => Ok(Ticket {
+                id,
+                reply: reply_rx,
+            }),
+            Err(TrySendError::Full(_)) => Err(SubmitError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => Err(SubmitError::Closed),
+        }
+    }
+}
+
+impl JobReceiver {
+    /// Waits for the next thing to do. Health checks come first so that they
+    /// are not delayed by a burst of jobs, followed by jobs of high priority.
+    /// `None` is returned when all the queues are closed.
+    pub fn next(&self, tick: &Receiver<Instant>) -> Option<Next> {
+        if tick.try_recv().is_ok() {
+            return Some(Next::HealthCheck);
+        }
+        if let Ok(job) = self.high.try_recv() {
+            return Some(Next::Job(job));
+        }
+
+        select! {
+            recv(tick) -> _ => Some(Next::HealthCheck),
+            recv(self.high) -> job => job.ok().map(Next::Job),
+            recv(self.normal) -> job => job.ok().map(Next::Job),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority() {
+        let (queue, receiver, _) = queue();
+        let (tick_tx, tick_rx) = channel::unbounded();
+
+        let first = queue.submit("list".to_string(), Priority::Normal).unwrap();
+        let second = queue.submit("stop".to_string(), Priority::High).unwrap();
+        assert_eq!(first.id + 1, second.id);
+        tick_tx.send(Instant::now()).unwrap();
+
+        assert!(matches!(receiver.next(&tick_rx), Some(Next::HealthCheck)));
+        assert!(matches!(
+            receiver.next(&tick_rx),
+            Some(Next::Job(job)) if job.command == "stop"
+        ));
+        assert!(matches!(
+            receiver.next(&tick_rx),
+            Some(Next::Job(job)) if job.command == "list"
+        ));
+    }
+
+    #[test]
+    fn test_queue_full() {
+        let (queue, _receiver, _) = queue();
+        for _ in 0..QUEUE_LEN {
+            queue.submit("list".to_string(), Priority::Normal).unwrap();
+        }
+        assert_eq!(
+            SubmitError::QueueFull,
+            queue
+                .submit("list".to_string(), Priority::Normal)
+                .unwrap_err()
+        );
+        assert!(queue.submit("stop".to_string(), Priority::High).is_ok());
+    }
+
+    #[test]
+    fn test_backup() {
+        let (queue, _receiver, backup_rx) = queue();
+        let id = queue.backup().unwrap();
+        assert_eq!(SubmitError::QueueFull, queue.backup().unwrap_err());
+        assert_eq!(id, backup_rx.try_recv().unwrap().id);
+
+        drop(backup_rx);
+        assert_eq!(SubmitError::Closed, queue.backup().unwrap_err());
+    }
+}
@@ -0,0 +1,211 @@
+use premises_config::v1::{self, FlagPreset};
+use std::fs;
+use std::io;
+
+/// Smallest heap to give the server even on a small machine.
+const MIN_HEAP_MB: u64 = 512;
+/// Aikar's flags are adjusted for heaps larger than this.
+const LARGE_HEAP_MB: u64 = 12 * 1024;
+
+/// Takes the total memory in MiB from the contents of `/proc/meminfo`.
+fn parse_meminfo(text: &str) -> Option<u64> {
+    let line = text.lines().find(|line| line.starts_with("MemTotal:"))?;
+    let kib: u64 = line
+        .trim_start_matches("MemTotal:")
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kib / 1024)
+}
+
+fn total_memory_mb() -> io::Result<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    parse_meminfo(&meminfo)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "MemTotal is not available"))
+}
+
+/// Takes the memory left after the reserved memory, which must fit the
+/// smallest heap.
+fn heap_mb(total_mb: u64, reserved_mb: u64) -> io::Result<u64> {
+    let heap_mb = total_mb.saturating_sub(reserved_mb);
+    if heap_mb < MIN_HEAP_MB {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} MiB of memory leaves {} MiB after reserving {} MiB, less than the {} MiB heap",
+                total_mb, heap_mb, reserved_mb, MIN_HEAP_MB
+            ),
+        ));
+    }
+    Ok(heap_mb)
+}
+
+/// Takes the size in MiB from an `-Xmx` argument such as `-Xmx4G`.
+fn parse_max_heap(arg: &str) -> Option<u64> {
+    let size = arg.strip_prefix("-Xmx")?;
+    let (digits, unit) = match size.char_indices().last()? {
+        (i, unit) if unit.is_ascii_alphabetic() => (&size[..i], Some(unit)),
+        _ => (size, None),
+    };
+    let value: u64 = digits.parse().ok()?;
+    match unit.map(|unit| unit.to_ascii_lowercase()) {
+        None => Some(value / (1024 * 1024)),
+        Some('k') => Some(value / 1024),
+        Some('m') => Some(value),
+        Some('g') => value.checked_mul(1024),
+        Some('t') => value.checked_mul(1024 * 1024),
+        Some(_) => None,
+    }
+}
+
+/// Flags recommended by Aikar for G1 on Minecraft servers.
+fn aikar_flags(heap_mb: u64) -> Vec<String> {
+    let large = heap_mb > LARGE_HEAP_MB;
+    let (new_size, max_new_size, region_size, reserve, occupancy) = if large {
+        (40, 50, "16M", 15, 20)
+    } else {
+        (30, 40, "8M", 20, 15)
+    };
+    vec![
+        "-XX:+UseG1GC".to_string(),
+        "-XX:+ParallelRefProcEnabled".to_string(),
+        "-XX:MaxGCPauseMillis=200".to_string(),
+        "-XX:+UnlockExperimentalVMOptions".to_string(),
+        "-XX:+DisableExplicitGC".to_string(),
+        "-XX:+AlwaysPreTouch".to_string(),
+        format!("-XX:G1NewSizePercent={}", new_size),
+        format!("-XX:G1MaxNewSizePercent={}", max_new_size),
+        format!("-XX:G1HeapRegionSize={}", region_size),
+        format!("-XX:G1ReservePercent={}", reserve),
+        "-XX:G1HeapWastePercent=5".to_string(),
+        "-XX:G1MixedGCCountTarget=4".to_string(),
+        format!("-XX:InitiatingHeapOccupancyPercent={}", occupancy),
+        "-XX:G1MixedGCLiveThresholdPercent=90".to_string(),
+        "-XX:G1RSetUpdatingPauseTimePercent=5".to_string(),
+        "-XX:SurvivorRatio=32".to_string(),
+        "-XX:+PerfDisableSharedMem".to_string(),
+        "-XX:MaxTenuringThreshold=1".to_string(),
+        "-Daikars.new.flags=true".to_string(),
+        "-Dusing.aikars.flags=https://mcflags.emc.gs".to_string(),
+    ]
+}
+
+/// Builds the JVM arguments from the heap size, the flag preset and the
+/// arguments in the config, in this order. `total_mb` is the memory of the
+/// system, read only when the heap size is computed from it. Aikar's flags
+/// follow the `-Xmx` in the config if there is one.
+fn build_args<F>(config: &v1::Java, total_mb: F) -> io::Result<Vec<String>>
+where
+    F: FnOnce() -> io::Result<u64>,
+{
+    let sets_heap = config
+        .jvm_args
+        .iter()
+        .any(|arg| arg.starts_with("-Xmx") || arg.starts_with("-Xms"));
+    let max_heap = config
+        .jvm_args
+        .iter()
+        .rev()
+        .find_map(|arg| parse_max_heap(arg));
+    let aikar = config.flags == FlagPreset::Aikar;
+    let heap_mb = match (max_heap, config.memory.heap_mb) {
+        _ if sets_heap && !aikar => None,
+        (Some(heap_mb), _) | (None, Some(heap_mb)) => Some(heap_mb),
+        (None, None) => Some(heap_mb(total_mb()?, config.memory.reserved_mb)?),
+    };
+
+    let mut args = Vec::new();
+    if let Some(heap_mb) = heap_mb {
+        if !sets_heap {
+            args.push(format!("-Xms{}M", heap_mb));
+            args.push(format!("-Xmx{}M", heap_mb));
+        }
+        if aikar {
+            args.extend(aikar_flags(heap_mb));
+        }
+    }
+    args.extend(config.jvm_args.iter().cloned());
+    Ok(args)
+}
+
+/// Builds the JVM arguments for the server.
+pub fn args(config: &v1::Java) -> io::Result<Vec<String>> {
+    build_args(config, total_memory_mb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn java(heap_mb: Option<u64>, flags: FlagPreset, jvm_args: &[&str]) -> v1::Java {
+        v1::Java {
+            memory: v1::Memory {
+                heap_mb,
+                reserved_mb: 1024,
+            },
+            flags,
+            jvm_args: jvm_args.iter().map(|arg| arg.to_string()).collect(),
+            ..v1::Java::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:        8148280 kB\n\
+                       MemFree:          577528 kB\n";
+        assert_eq!(Some(7957), parse_meminfo(meminfo));
+        assert_eq!(None, parse_meminfo("MemFree: 577528 kB\n"));
+    }
+
+    #[test]
+    fn test_heap_mb() {
+        assert_eq!(7168, heap_mb(8192, 1024).unwrap());
+        assert_eq!(MIN_HEAP_MB, heap_mb(1536, 1024).unwrap());
+        assert!(heap_mb(1024, 1024).is_err());
+        assert!(heap_mb(512, 1024).is_err());
+    }
+
+    #[test]
+    fn test_parse_max_heap() {
+        assert_eq!(Some(4096), parse_max_heap("-Xmx4G"));
+        assert_eq!(Some(2048), parse_max_heap("-Xmx2048m"));
+        assert_eq!(Some(1), parse_max_heap("-Xmx1024k"));
+        assert_eq!(Some(2), parse_max_heap("-Xmx2097152"));
+        assert_eq!(None, parse_max_heap("-Xms4G"));
+        assert_eq!(None, parse_max_heap("-Xmx4X"));
+        assert_eq!(None, parse_max_heap("-Xmx"));
+    }
+
+    #[test]
+    fn test_build_args() {
+        let args = build_args(&java(None, FlagPreset::None, &["-Dfoo=bar"]), || Ok(4096)).unwrap();
+        assert_eq!(vec!["-Xms3072M", "-Xmx3072M", "-Dfoo=bar"], args);
+
+        let args = build_args(&java(Some(16384), FlagPreset::Aikar, &[]), || {
+            panic!("memory is read")
+        })
+        .unwrap();
+        assert_eq!(&["-Xms16384M", "-Xmx16384M", "-XX:+UseG1GC"], &args[..3]);
+        assert!(args.contains(&"-XX:G1HeapRegionSize=16M".to_string()));
+
+        let args = build_args(&java(None, FlagPreset::None, &["-Xmx2G"]), || {
+            panic!("memory is read")
+        })
+        .unwrap();
+        assert_eq!(vec!["-Xmx2G"], args);
+
+        let args = build_args(
+            &java(None, FlagPreset::Aikar, &["-Xms16G", "-Xmx16G"]),
+            || panic!("memory is read"),
+        )
+        .unwrap();
+        assert_eq!("-XX:+UseG1GC", args[0]);
+        assert!(args.contains(&"-XX:G1HeapRegionSize=16M".to_string()));
+        assert_eq!(&["-Xms16G", "-Xmx16G"], &args[args.len() - 2..]);
+
+        let err = build_args(&java(None, FlagPreset::None, &[]), || Ok(1024)).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+}
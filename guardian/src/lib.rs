@@ -0,0 +1,7 @@
+pub mod command;
+pub mod console;
+pub mod net;
+pub mod properties;
+pub mod query;
+pub mod rcon;
+pub mod slp;
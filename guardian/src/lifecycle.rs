@@ -0,0 +1,148 @@
+use crate::monitor::{Event, EventKind};
+use crate::restart::Exit;
+use crossbeam_channel::Sender;
+use guardian::console::LogEvent;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+
+/// Lifecycle state of the server process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum State {
+    /// Setting up files before launching the server.
+    Preparing,
+    /// Launched but not ready yet, with progress of preparing the spawn area
+    /// in percent.
+    Starting(u8),
+    Online,
+    Stopping,
+    Stopped,
+    /// Exited abnormally.
+    Crashed(Exit),
+    /// Waiting to launch the server again after it exited.
+    Restarting,
+    /// Crashed too often, so the server is not restarted anymore.
+    CrashLoop,
+}
+
+impl State {
+    pub fn is_starting(&self) -> bool {
+        matches!(self, Self::Starting(_))
+    }
+}
+
+/// Tracks the lifecycle state shared among the threads watching the server,
+/// sending an event only when the state changes.
+#[derive(Debug, Clone)]
+pub struct Lifecycle {
+    state: Arc<Mutex<State>>,
+    /// Process id of the server while it is running.
+    pid: Arc<Mutex<Option<u32>>>,
+    out_ev: Sender<Event>,
+}
+
+impl Lifecycle {
+    pub fn new(out_ev: Sender<Event>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::Stopped)),
+            pid: Arc::new(Mutex::new(None)),
+            out_ev,
+        }
+    }
+
+    pub fn pid(&self) -> Option<u32> {
+        *self.pid.lock().unwrap()
+    }
+
+    pub fn set_pid(&self, pid: Option<u32>) {
+        *self.pid.lock().unwrap() = pid;
+    }
+
+    pub fn state(&self) -> State {
+        *self.state.lock().unwrap()
+    }
+
+    /// Moves to `next` if the current state satisfies `pred`.
+    pub fn transition_if<F>(&self, pred: F, next: State) -> bool
+    where
+        F: FnOnce(&State) -> bool,
+    {
+        let mut state = self.state.lock().unwrap();
+        if *state == next || !pred(&state) {
+            return false;
+        }
+
+        *state = next;
+        // Sending while locked keeps events in the same order as transitions.
+        self.out_ev
+            .send(Event::new(EventKind::StateChanged(next)))
+            .unwrap();
+        true
+    }
+
+    pub fn transition(&self, next: State) -> bool {
+        self.transition_if(|_| true, next)
+    }
+
+    /// Follows the progress the server reports in its output.
+    pub fn handle_log_event(&self, event: &LogEvent) {
+        match event {
+            LogEvent::Starting(progress) => {
+                self.transition_if(
+                    |state| matches!(state, State::Preparing | State::Starting(_)),
+                    State::Starting(*progress),
+                );
+            }
+            LogEvent::Done(_) => {
+                self.transition_if(State::is_starting, State::Online);
+            }
+            LogEvent::Stopping => {
+                self.transition(State::Stopping);
+            }
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_channel as channel;
+    use std::time::Duration;
+
+    #[test]
+    fn test_transition() {
+        let (tx, rx) = channel::unbounded();
+        let lifecycle = Lifecycle::new(tx);
+
+        assert!(lifecycle.transition(State::Preparing));
+        assert!(!lifecycle.transition(State::Preparing));
+        assert!(lifecycle.transition(State::Starting(0)));
+        lifecycle.handle_log_event(&LogEvent::Starting(0));
+        lifecycle.handle_log_event(&LogEvent::Starting(42));
+        lifecycle.handle_log_event(&LogEvent::Done(Duration::from_secs(1)));
+        lifecycle.handle_log_event(&LogEvent::Starting(100));
+        assert!(!lifecycle.transition_if(State::is_starting, State::Online));
+        lifecycle.handle_log_event(&LogEvent::Stopping);
+        assert!(lifecycle.transition(State::Crashed(Exit::Code(1))));
+
+        let states: Vec<_> = rx
+            .try_iter()
+            .map(|ev| match ev.kind {
+                EventKind::StateChanged(state) => state,
+                kind => panic!("unexpected event: {:?}", kind),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                State::Preparing,
+                State::Starting(0),
+                State::Starting(42),
+                State::Online,
+                State::Stopping,
+                State::Crashed(Exit::Code(1)),
+            ],
+            states
+        );
+    }
+}
@@ -1,125 +1,280 @@
-mod rcon;
+mod api;
+mod backup;
+mod download;
+mod history;
+mod idle;
+mod java;
+mod job;
+mod jvm;
+mod lifecycle;
+mod metrics;
+mod monitor;
+mod restart;
+mod secret;
+mod shutdown;
+mod supervisor;
 
+use api::Api;
+use backup::Backups;
 use crossbeam_channel as channel;
-use log::info;
-use monitor::start_monitoring;
-use std::fs::{create_dir_all, File};
-use std::io::prelude::*;
-use std::process::{Command, Stdio};
-use std::{thread, time};
+use download::Downloader;
+use guardian::rcon::Timeouts;
+use history::History;
+use lifecycle::Lifecycle;
+use log::{error, info};
+use metrics::Sampler;
+use monitor::{start_monitoring, Event, EventKind, Session, StatusProbe};
+use premises_config::{v1, Config};
+use secret::Password;
+use std::error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+use std::{env, thread, time};
+use supervisor::Supervisor;
 
-fn agree_eula() -> std::io::Result<()> {
-    create_dir_all("/tmp/m")?;
+/// Number of recent events kept for the API.
+const HISTORY_LEN: usize = 256;
 
-    let mut out = File::create("/tmp/m/eula.txt")?;
-    write!(out, "eula=true")?;
-
-    Ok(())
+/// Takes the path to the config from the first argument, or from
+/// `PREMISES_CONFIG`.
+fn config_path() -> Option<PathBuf> {
+    env::args_os()
+        .nth(1)
+        .or_else(|| env::var_os("PREMISES_CONFIG"))
+        .map(PathBuf::from)
 }
 
-mod monitor {
-    use crossbeam_channel::{self as channel, select, Receiver, Sender};
-    use log::debug;
-    use std::{net::TcpStream, time};
+fn load_config(path: &Path) -> Result<Config, Box<dyn error::Error>> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(BufReader::new(file))?)
+}
 
-    use crate::rcon::RconClient;
+/// Formats a metric which may not have been measured.
+fn or_unknown(value: Option<u64>) -> String {
+    value.map_or_else(|| "?".to_string(), |value| value.to_string())
+}
 
-    #[derive(Debug)]
-    pub enum Event {
-        Online,
-        Offline,
+fn print_event(ev: &Event) {
+    let time = ev.time.duration_since(UNIX_EPOCH).unwrap().as_millis();
+    match &ev.kind {
+        EventKind::StateChanged(state) => println!("[{}] State: {:?}", time, state),
+        EventKind::Status(status) => println!(
+            "[{}] Status: {} ({}/{} players, {} ms) {}",
+            time,
+            status.version,
+            status.players_online,
+            status.players_max,
+            status.latency.as_millis(),
+            status.motd
+        ),
+        EventKind::HealthChanged(health) => println!("[{}] Health: {:?}", time, health),
+        EventKind::Metrics(metrics) => println!(
+            "[{}] Metrics: CPU {:.1}%, RSS {} MiB, {} threads, {} fds, world {} MiB, {} MiB free",
+            time,
+            metrics.cpu_percent.unwrap_or_default(),
+            metrics.rss_bytes / 1024 / 1024,
+            metrics.threads,
+            or_unknown(metrics.open_fds),
+            or_unknown(metrics.world_bytes.map(|bytes| bytes / 1024 / 1024)),
+            or_unknown(metrics.disk_free_bytes.map(|bytes| bytes / 1024 / 1024))
+        ),
+        EventKind::Console(ev) => println!("[{}] Console: {:?}", time, ev),
+        EventKind::BackupCreated(manifest) => println!(
+            "[{}] Backup: {} ({} bytes, sha256 {})",
+            time, manifest.name, manifest.size, manifest.sha256
+        ),
+        EventKind::BackupFailed(message) => println!("[{}] Backup failed: {}", time, message),
+        kind => println!("[{}] {:?}", time, kind),
     }
+}
 
-    fn try_execute_command(cmd: &str) -> Option<String> {
-        let transport = match TcpStream::connect(("localhost", 25575)) {
-            Ok(transport) => transport,
-            Err(err) => {
-                debug!("Failed to connect to RCON: {}", err);
-                return None;
-            }
-        };
+fn main() {
+    env_logger::init();
 
-        let mut rcon = RconClient::new(transport, false);
-        rcon.authenticate("x").unwrap();
-        rcon.execute(cmd).ok()
-    }
+    let Some(path) = config_path() else {
+        eprintln!("Usage: guardian <config> (or set PREMISES_CONFIG)");
+        process::exit(2);
+    };
+    let config = match load_config(&path) {
+        Ok(config) => config,
+        Err(err) => {
+            error!("Failed to load config from {}: {}", path.display(), err);
+            process::exit(1);
+        }
+    };
 
-    fn handle_event(event: &str) {
-        try_execute_command(event);
+    match config {
+        Config::V1(config) => process::exit(run(config)),
     }
+}
 
-    fn do_health_check(out_ev: &Sender<Event>) {
-        match try_execute_command("list") {
-            Some(_) => out_ev.send(Event::Online).unwrap(),
-            None => out_ev.send(Event::Offline).unwrap(),
-        };
+fn run(mut config: v1::Config) -> i32 {
+    if let Err(err) = supervisor::check_server_ip(&config) {
+        error!("Invalid config: {}", err);
+        return 1;
     }
-
-    pub fn start_monitoring(in_ev: Receiver<&str>, out_ev: Sender<Event>) {
-        let monitor_tick = channel::tick(time::Duration::from_secs(5));
-        loop {
-            select! {
-                recv(in_ev) -> job => {
-                    if let Ok(job) = job {
-                        handle_event(job);
-                    }
+    let downloader = Downloader::new(&config.download, &config.work_dir);
+    let java_version = match &config.server.jar {
+        Some(jar) => downloader.java_version(jar, &config.server.version),
+        None => {
+            // The server runs in the working directory, so a relative path to
+            // the cache would not point to the jar.
+            let jar = downloader
+                .server_jar(&config.server.version)
+                .and_then(|jar| Ok((fs::canonicalize(jar.path)?, jar.java_version)));
+            match jar {
+                Ok((jar, version)) => {
+                    config.server.jar = Some(jar);
+                    version
+                }
+                Err(err) => {
+                    error!("Failed to get server jar: {}", err);
+                    return 1;
                 }
-                recv(monitor_tick) -> _ => {
-                    do_health_check(&out_ev);
+            }
+        }
+    };
+    if config.java.path.is_none() {
+        let runtimes = java::discover(&config.java.search_dirs);
+        match java::select(&runtimes, java_version, config.java.allow_newer) {
+            Some(runtime) => {
+                info!(
+                    "Using Java {} at {}",
+                    runtime.version,
+                    runtime.path.display()
+                );
+                config.java.path = Some(runtime.path.clone());
+            }
+            None => {
+                match java_version {
+                    Some(version) if config.java.allow_newer => {
+                        error!("No Java runtime of version {} or later found", version)
+                    }
+                    Some(version) => error!(
+                        "No Java {} runtime found; set java.allow_newer to use a newer one",
+                        version
+                    ),
+                    None => error!("No Java runtime found"),
                 }
-            };
+                return 1;
+            }
+        }
+    }
+    match jvm::args(&config.java) {
+        Ok(args) => {
+            info!("JVM arguments: {}", args.join(" "));
+            config.java.jvm_args = args;
+        }
+        Err(err) => {
+            error!("Failed to size the heap: {}", err);
+            return 1;
         }
     }
-}
-
-fn main() {
-    env_logger::init();
 
-    let (job_tx, job_rx) = channel::unbounded();
-    let (event_tx, event_rx) = channel::unbounded::<monitor::Event>();
+    let signals = match shutdown::watch_signals() {
+        Ok(signals) => signals,
+        Err(err) => {
+            error!("Failed to watch signals: {}", err);
+            return 1;
+        }
+    };
+    let (jobs, job_rx, backup_rx) = job::queue();
+    let (event_tx, event_rx) = channel::unbounded::<Event>();
+    let (control_tx, control_rx) = channel::unbounded();
 
-    // debug
+    let history = Arc::new(Mutex::new(History::new(HISTORY_LEN)));
+    let event_history = history.clone();
     thread::spawn(move || {
-        thread::sleep(time::Duration::from_secs(20));
-        job_tx.send("op hogehogefugafuga").unwrap();
-        thread::sleep(time::Duration::from_secs(2));
-        job_tx.send("stop").unwrap();
-
-        loop {
-            thread::sleep(time::Duration::from_secs(20));
+        for ev in event_rx {
+            print_event(&ev);
+            event_history.lock().unwrap().push(ev);
         }
     });
-    thread::spawn(move || loop {
-        if let Ok(ev) = event_rx.recv() {
-            println!("{:?}", ev);
-        };
-    });
-
-    let job_thread = thread::spawn(move || start_monitoring(job_rx.clone(), event_tx.clone()));
-
-    agree_eula().unwrap();
 
-    loop {
-        let result = Command::new("/tmp/mcserver-mock")
-            .args(["-jar", "/tmp/server.jar", "nogui"])
-            .current_dir("/tmp/m")
-            .stdin(Stdio::inherit())
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()
-            .unwrap()
-            .wait()
-            .unwrap();
-
-        if result.success() {
-            break;
-        }
+    if let Some(timeout_secs) = config.idle.timeout_secs {
+        let idle_history = history.clone();
+        let idle_tx = control_tx.clone();
+        thread::spawn(move || {
+            idle::watch_idle(
+                time::Duration::from_secs(timeout_secs),
+                idle_history,
+                idle_tx,
+            )
+        });
+    }
 
-        info!(
-            "Minecraft server exitted abnormally (code: {}). Restarting...",
-            result.code().unwrap()
+    if config.api.enabled {
+        let api = Api::new(
+            history.clone(),
+            jobs.clone(),
+            control_tx,
+            config.work_dir.join(&config.backup.dir),
+            config.api.token.clone(),
         );
+        if let Err(err) = api::start(&config.api, api) {
+            error!("Failed to start API on {}: {}", config.api.bind, err);
+            return 1;
+        }
     }
 
-    job_thread.join().unwrap();
+    let timeouts = Timeouts {
+        connect: Some(time::Duration::from_secs(3)),
+        read: Some(time::Duration::from_secs(3)),
+        write: Some(time::Duration::from_secs(3)),
+    };
+    let rcon_password = Password::default();
+    let session = config.rcon.enabled.then(|| {
+        Session::new(
+            "localhost",
+            config.rcon.port,
+            rcon_password.clone(),
+            timeouts,
+        )
+    });
+    let probe = StatusProbe::new(
+        "localhost",
+        config.server.port,
+        config.server.query_port,
+        timeouts,
+    );
+    let lifecycle = Lifecycle::new(event_tx.clone());
+    let sampler = Sampler::new(&config.work_dir);
+    let monitor_lifecycle = lifecycle.clone();
+    let monitor_tx = event_tx.clone();
+    thread::spawn(move || {
+        start_monitoring(
+            session,
+            probe,
+            sampler,
+            monitor_lifecycle,
+            job_rx,
+            monitor_tx,
+        )
+    });
+
+    let backups = Backups::new(
+        &config,
+        lifecycle.clone(),
+        config.rcon.enabled.then(|| jobs.clone()),
+        history,
+        event_tx.clone(),
+    );
+    let backup_worker = backups.clone();
+    thread::spawn(move || backup::start_backups(backup_worker, backup_rx));
+
+    Supervisor::new(
+        &config,
+        lifecycle,
+        event_tx,
+        jobs,
+        rcon_password,
+        signals,
+        control_rx,
+        backups,
+    )
+    .run()
 }
@@ -0,0 +1,233 @@
+use crate::backup;
+use log::debug;
+use serde::Serialize;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Interval of measuring the size of the worlds, which walks through all of
+/// their files.
+const WORLD_SIZE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Resource usage of the server process and its files. Values that could not
+/// be measured are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Metrics {
+    /// CPU time spent in user and kernel mode, in milliseconds.
+    pub cpu_time_ms: u64,
+    /// CPU time spent since the previous sample per elapsed time, in percent
+    /// of a core.
+    pub cpu_percent: Option<f64>,
+    pub rss_bytes: u64,
+    pub threads: u64,
+    pub open_fds: Option<u64>,
+    /// Measured once a minute, and repeated in the samples between.
+    pub world_bytes: Option<u64>,
+    /// Space available to the server on the disk of the working directory.
+    pub disk_free_bytes: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Stat {
+    /// CPU time in clock ticks.
+    cpu_ticks: u64,
+    threads: u64,
+    rss_pages: u64,
+}
+
+/// Parses `/proc/<pid>/stat`. The name of the command may contain spaces and
+/// parentheses, so fields are counted from the last parenthesis.
+fn parse_stat(text: &str) -> Option<Stat> {
+    let (_, rest) = text.rsplit_once(')')?;
+    // The state, which is the third field, comes first.
+    let fields: Vec<_> = rest.split_whitespace().collect();
+    let field = |n: usize| fields.get(n - 3)?.parse::<u64>().ok();
+    Some(Stat {
+        cpu_ticks: field(14)? + field(15)?,
+        threads: field(20)?,
+        rss_pages: field(24)?,
+    })
+}
+
+/// Total size of the files under `path`. Files removed while walking are
+/// skipped, since the server may be saving the world.
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+
+    let mut size = 0;
+    for entry in entries {
+        let entry = entry?;
+        let metadata = match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+fn world_size(work_dir: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for world in backup::world_dirs(work_dir)? {
+        size += dir_size(&work_dir.join(world))?;
+    }
+    Ok(size)
+}
+
+fn disk_free(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is a valid C string and `stat` is only read when the
+    // call filled it.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        stat.assume_init()
+    };
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Keeps the other metrics when one of them cannot be measured.
+fn measured<T>(name: &str, result: io::Result<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            debug!("Failed to measure {}: {}", name, err);
+            None
+        }
+    }
+}
+
+fn sysconf(name: libc::c_int) -> u64 {
+    // SAFETY: sysconf has no preconditions.
+    unsafe { libc::sysconf(name) as u64 }
+}
+
+/// Takes samples of metrics, keeping the previous one to compute the CPU
+/// usage.
+#[derive(Debug)]
+pub struct Sampler {
+    work_dir: PathBuf,
+    clock_ticks: u64,
+    page_size: u64,
+    /// Process id, time and CPU time of the previous sample.
+    previous: Option<(u32, Instant, u64)>,
+    /// Last size of the worlds and when it was measured.
+    world_size: Option<(Instant, Option<u64>)>,
+}
+
+impl Sampler {
+    pub fn new(work_dir: &Path) -> Self {
+        Self {
+            work_dir: work_dir.to_path_buf(),
+            clock_ticks: sysconf(libc::_SC_CLK_TCK),
+            page_size: sysconf(libc::_SC_PAGESIZE),
+            previous: None,
+            world_size: None,
+        }
+    }
+
+    pub fn sample(&mut self, pid: u32) -> io::Result<Metrics> {
+        let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+        let stat = parse_stat(&fs::read_to_string(proc_dir.join("stat"))?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid stat"))?;
+        let open_fds = fs::read_dir(proc_dir.join("fd")).map(|entries| entries.count() as u64);
+
+        let now = Instant::now();
+        let cpu_time_ms = stat.cpu_ticks * 1000 / self.clock_ticks;
+        let cpu_percent = match self.previous {
+            Some((previous_pid, time, previous_ms)) if previous_pid == pid => {
+                let elapsed_ms = now.duration_since(time).as_millis();
+                (elapsed_ms > 0).then(|| {
+                    cpu_time_ms.saturating_sub(previous_ms) as f64 * 100.0 / elapsed_ms as f64
+                })
+            }
+            _ => None,
+        };
+        self.previous = Some((pid, now, cpu_time_ms));
+
+        let world_bytes = match self.world_size {
+            Some((time, size)) if now.duration_since(time) < WORLD_SIZE_INTERVAL => size,
+            _ => {
+                let size = measured("world size", world_size(&self.work_dir));
+                self.world_size = Some((now, size));
+                size
+            }
+        };
+
+        Ok(Metrics {
+            cpu_time_ms,
+            cpu_percent,
+            rss_bytes: stat.rss_pages * self.page_size,
+            threads: stat.threads,
+            open_fds: measured("open files", open_fds),
+            world_bytes,
+            disk_free_bytes: measured("free disk space", disk_free(&self.work_dir)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "4242 (java (server)) S 1 4242 4242 0 -1 4194560 125435 0 12 0 \
+                    1520 380 0 0 20 0 48 0 1234567 5368709120 262144 18446744073709551615\n";
+        assert_eq!(
+            Some(Stat {
+                cpu_ticks: 1900,
+                threads: 48,
+                rss_pages: 262144,
+            }),
+            parse_stat(stat)
+        );
+        assert_eq!(None, parse_stat("4242 (java) S 1"));
+    }
+
+    #[test]
+    fn test_sample() {
+        let work_dir = std::env::temp_dir().join(format!("guardian-metrics-{}", process::id()));
+        fs::create_dir_all(work_dir.join("world/region")).unwrap();
+        fs::write(work_dir.join("world/level.dat"), [0; 100]).unwrap();
+        fs::write(work_dir.join("world/region/r.0.0.mca"), [0; 4096]).unwrap();
+
+        let mut sampler = Sampler::new(&work_dir);
+        let first = sampler.sample(process::id()).unwrap();
+        fs::write(work_dir.join("world/session.lock"), [0; 4]).unwrap();
+        let second = sampler.sample(process::id()).unwrap();
+        fs::remove_dir_all(&work_dir).unwrap();
+
+        assert_eq!(Some(4196), first.world_bytes);
+        assert!(first.rss_bytes > 0);
+        assert!(first.threads > 0);
+        assert!(first.open_fds > Some(0));
+        assert!(first.disk_free_bytes > Some(0));
+        assert_eq!(None, first.cpu_percent);
+        assert!(second.cpu_time_ms >= first.cpu_time_ms);
+        // The size of the worlds is not measured again so soon.
+        assert_eq!(Some(4196), second.world_bytes);
+
+        // The working directory is missing, but the process is still there.
+        let metrics = Sampler::new(&work_dir).sample(process::id()).unwrap();
+        assert_eq!(None, metrics.disk_free_bytes);
+        assert!(metrics.rss_bytes > 0);
+    }
+}
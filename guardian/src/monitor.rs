@@ -0,0 +1,590 @@
+use crate::backup::Manifest;
+use crate::job::{Job, JobReceiver, Next};
+use crate::lifecycle::{Lifecycle, State};
+use crate::metrics::{Metrics, Sampler};
+use crate::secret::Password;
+use crossbeam_channel::{self as channel, Sender};
+use guardian::command::{self, Command};
+use guardian::console::LogEvent;
+use guardian::query::{self, QueryClient};
+use guardian::rcon::{self, RconClient, Timeouts};
+use guardian::slp::{self, SlpClient};
+use log::{debug, error, warn};
+use serde::Serialize;
+use std::fmt;
+use std::net::TcpStream;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Result of a health check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Healthy,
+    /// The server accepts connections but does not respond in time.
+    Unhealthy,
+    Unreachable,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// The server moved to another lifecycle state.
+    StateChanged(State),
+    /// The result of health checks changed.
+    HealthChanged(Health),
+    /// Periodic status of the server while it answers Server List Ping.
+    Status(slp::Status),
+    /// Listing players started failing while the server is reachable, so the
+    /// players in the status are only the sample from Server List Ping.
+    PlayerListFailed(String),
+    /// Periodic resource usage while the server is running.
+    Metrics(Metrics),
+    AuthenticationFailed,
+    /// Recognized line in the output of the server.
+    Console(LogEvent),
+    BackupCreated(Manifest),
+    BackupFailed(String),
+    /// The server started with the worlds of the backup.
+    Restored(String),
+    /// The worlds were rolled back, or were left as they were.
+    RestoreFailed(String),
+    /// Launching, watching or stopping the server process failed.
+    ProcessFailed(String),
+    /// The server was stopped since no players were online, and can be torn
+    /// down.
+    IdleShutdown,
+}
+
+#[derive(Debug)]
+pub struct Event {
+    pub time: SystemTime,
+    pub kind: EventKind,
+}
+
+impl Event {
+    pub fn new(kind: EventKind) -> Self {
+        Self {
+            time: SystemTime::now(),
+            kind,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    /// RCON is disabled in the config.
+    Disabled,
+    /// Connecting is suspended until the backoff period passes.
+    Backoff,
+    /// Failed to connect, so the command was not sent.
+    Connect(rcon::Error),
+    Authentication(String),
+    Rcon(rcon::Error),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => write!(f, "RCON is disabled"),
+            Self::Backoff => write!(f, "Waiting before reconnecting"),
+            Self::Connect(err) => write!(f, "Failed to connect: {}", err),
+            Self::Authentication(message) => write!(f, "Authentication failed: {}", message),
+            Self::Rcon(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    current: Duration,
+    retry_at: Option<Instant>,
+}
+
+impl Backoff {
+    fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            current: initial,
+            retry_at: None,
+        }
+    }
+
+    fn is_waiting(&self, now: Instant) -> bool {
+        matches!(self.retry_at, Some(retry_at) if now < retry_at)
+    }
+
+    fn fail(&mut self, now: Instant) {
+        self.retry_at = Some(now + self.current);
+        self.current = (self.current * 2).min(self.max);
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+        self.retry_at = None;
+    }
+}
+
+/// Checks whether players can connect to the server with Server List Ping,
+/// optionally listing players with Query.
+#[derive(Debug)]
+pub struct StatusProbe {
+    host: String,
+    port: u16,
+    query_port: Option<u16>,
+    timeouts: Timeouts,
+}
+
+impl StatusProbe {
+    pub fn new(host: &str, port: u16, query_port: Option<u16>, timeouts: Timeouts) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            query_port,
+            timeouts,
+        }
+    }
+
+    fn check(&self) -> Result<slp::Status, slp::Error> {
+        SlpClient::connect(&self.host, self.port, self.timeouts)?.status()
+    }
+
+    fn query_players(&self) -> Option<Result<Vec<String>, query::Error>> {
+        let query_port = self.query_port?;
+        let result = QueryClient::connect((self.host.as_str(), query_port), self.timeouts)
+            .and_then(|mut client| client.full_stat());
+        Some(result.map(|stat| stat.players))
+    }
+}
+
+enum CheckFailure {
+    TimedOut,
+    Unavailable(String),
+}
+
+impl fmt::Display for CheckFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TimedOut => write!(f, "Timed out"),
+            Self::Unavailable(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Long-lived RCON session which reconnects when the connection is lost.
+#[derive(Debug)]
+pub struct Session {
+    host: String,
+    port: u16,
+    password: Password,
+    timeouts: Timeouts,
+    client: Option<RconClient<TcpStream>>,
+    backoff: Backoff,
+}
+
+impl Session {
+    pub fn new(host: &str, port: u16, password: Password, timeouts: Timeouts) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            password,
+            timeouts,
+            client: None,
+            backoff: Backoff::new(Duration::from_secs(1), Duration::from_secs(60)),
+        }
+    }
+
+    fn connect(&mut self) -> Result<RconClient<TcpStream>, SessionError> {
+        if self.backoff.is_waiting(Instant::now()) {
+            return Err(SessionError::Backoff);
+        }
+
+        let result = match RconClient::connect((self.host.as_str(), self.port), self.timeouts, true)
+        {
+            Ok(mut client) => match client.authenticate(&self.password.expose()) {
+                Ok(_) => Ok(client),
+                Err(rcon::Error::Upstream(message)) => Err(SessionError::Authentication(message)),
+                Err(err) => Err(SessionError::Connect(err)),
+            },
+            Err(err) => Err(SessionError::Connect(err)),
+        };
+
+        match result {
+            Ok(client) => {
+                self.backoff.reset();
+                Ok(client)
+            }
+            Err(err) => {
+                debug!("Failed to connect to RCON: {}", err);
+                self.backoff.fail(Instant::now());
+                Err(err)
+            }
+        }
+    }
+
+    /// Runs `cmd`, waiting for the response for `read_timeout` instead of
+    /// the read timeout of the session if given.
+    pub fn execute(
+        &mut self,
+        cmd: &str,
+        read_timeout: Option<Duration>,
+    ) -> Result<String, SessionError> {
+        // A connection kept from an earlier command may have been closed by
+        // the server since then.
+        let (mut client, reused) = match self.client.take() {
+            Some(client) if !client.is_closed() => (client, true),
+            Some(_) => {
+                debug!("RCON connection was closed, reconnecting");
+                (self.connect()?, false)
+            }
+            None => (self.connect()?, false),
+        };
+
+        // The timeout is set for every command, since the connection is kept.
+        let read_timeout = read_timeout.or(self.timeouts.read);
+        let execute = |client: &mut RconClient<TcpStream>| {
+            client.set_read_timeout(read_timeout)?;
+            client.execute(cmd)
+        };
+
+        let result = match execute(&mut client) {
+            // The connection may still have been lost right before, and then
+            // the command can be sent again as none of it went out. Once
+            // anything was sent, the server may have run the command, so the
+            // error is reported instead.
+            Err(rcon::Error::NotSent(err)) if reused => {
+                debug!("RCON connection lost, reconnecting: {}", err);
+                match self.connect() {
+                    Ok(new_client) => {
+                        client = new_client;
+                        execute(&mut client)
+                    }
+                    Err(_) => return Err(SessionError::Rcon(rcon::Error::NotSent(err))),
+                }
+            }
+            result => result,
+        };
+
+        match result {
+            Ok(response) => {
+                self.client = Some(client);
+                Ok(response)
+            }
+            // The stream may be left in the middle of a packet, so the
+            // connection is dropped on any error.
+            Err(err) => Err(SessionError::Rcon(err)),
+        }
+    }
+
+    pub fn run<C>(&mut self, cmd: &C) -> Result<C::Output, SessionError>
+    where
+        C: Command,
+    {
+        let response = self.execute(&cmd.to_command_line(), None)?;
+        cmd.parse_response(&response).map_err(SessionError::Rcon)
+    }
+}
+
+fn report_result<T>(
+    out_ev: &Sender<Event>,
+    result: Result<T, SessionError>,
+) -> Result<T, SessionError> {
+    match &result {
+        Ok(_) => (),
+        Err(SessionError::Authentication(message)) => {
+            error!("RCON authentication failed: {}", message);
+            out_ev
+                .send(Event::new(EventKind::AuthenticationFailed))
+                .unwrap();
+        }
+        Err(err) => debug!("Failed to execute command: {}", err),
+    }
+    result
+}
+
+fn handle_job(session: Option<&mut Session>, out_ev: &Sender<Event>, job: Job) {
+    let result = match session {
+        Some(session) => report_result(out_ev, session.execute(&job.command, job.timeout)),
+        None => {
+            debug!("RCON is disabled; ignoring command");
+            Err(SessionError::Disabled)
+        }
+    };
+    debug!("Job {} finished", job.id);
+    // The submitter may have stopped waiting.
+    let _ = job.reply.send(result);
+}
+
+/// Lists online players. Since the status only contains some of them, they
+/// are taken from RCON, or from Query when RCON is disabled.
+fn list_players(
+    session: Option<&mut Session>,
+    probe: &StatusProbe,
+    out_ev: &Sender<Event>,
+) -> Result<Option<Vec<String>>, CheckFailure> {
+    match session {
+        Some(session) => match report_result(out_ev, session.run(&command::List)) {
+            Ok(list) => Ok(Some(list.players)),
+            // The command was sent, but the server did not answer it in time.
+            Err(SessionError::Rcon(rcon::Error::Timeout)) => Err(CheckFailure::TimedOut),
+            Err(err) => Err(CheckFailure::Unavailable(err.to_string())),
+        },
+        None => match probe.query_players() {
+            Some(Ok(players)) => Ok(Some(players)),
+            Some(Err(err)) => {
+                debug!("Failed to query server: {}", err);
+                match err {
+                    query::Error::Timeout => Err(CheckFailure::TimedOut),
+                    err => Err(CheckFailure::Unavailable(err.to_string())),
+                }
+            }
+            None => Ok(None),
+        },
+    }
+}
+
+/// Checks the health of the server. Whether it is reachable is told by
+/// Server List Ping alone, so that failing to list players, such as while
+/// RCON is not up yet, does not hide a server players can join. The server is
+/// unhealthy when it takes too long to list players though, since the list is
+/// made on the main thread unlike the status. `players_failing` keeps whether
+/// listing players failed last time, to report it once.
+fn do_health_check(
+    session: Option<&mut Session>,
+    probe: &StatusProbe,
+    out_ev: &Sender<Event>,
+    players_failing: &mut bool,
+) -> Health {
+    let mut status = match probe.check() {
+        Ok(status) => status,
+        Err(err) => {
+            debug!("Failed to get server status: {}", err);
+            *players_failing = false;
+            return match err {
+                slp::Error::Timeout => Health::Unhealthy,
+                _ => Health::Unreachable,
+            };
+        }
+    };
+
+    let health = match list_players(session, probe, out_ev) {
+        Ok(players) => {
+            if let Some(players) = players {
+                status.players = players;
+            }
+            *players_failing = false;
+            Health::Healthy
+        }
+        Err(failure) => {
+            if !*players_failing {
+                *players_failing = true;
+                warn!("Failed to list players: {}", failure);
+                out_ev
+                    .send(Event::new(EventKind::PlayerListFailed(failure.to_string())))
+                    .unwrap();
+            }
+            match failure {
+                CheckFailure::TimedOut => Health::Unhealthy,
+                CheckFailure::Unavailable(_) => Health::Healthy,
+            }
+        }
+    };
+    out_ev.send(Event::new(EventKind::Status(status))).unwrap();
+    health
+}
+
+/// Starts monitoring the server. `session` is `None` when RCON is disabled.
+pub fn start_monitoring(
+    mut session: Option<Session>,
+    probe: StatusProbe,
+    mut sampler: Sampler,
+    lifecycle: Lifecycle,
+    jobs: JobReceiver,
+    out_ev: Sender<Event>,
+) {
+    let monitor_tick = channel::tick(Duration::from_secs(5));
+    let mut last_health = None;
+    let mut players_failing = false;
+    while let Some(next) = jobs.next(&monitor_tick) {
+        match next {
+            Next::Job(job) => handle_job(session.as_mut(), &out_ev, job),
+            Next::HealthCheck => {
+                if let Some(pid) = lifecycle.pid() {
+                    match sampler.sample(pid) {
+                        Ok(metrics) => out_ev
+                            .send(Event::new(EventKind::Metrics(metrics)))
+                            .unwrap(),
+                        Err(err) => debug!("Failed to sample metrics: {}", err),
+                    }
+                }
+                let health =
+                    do_health_check(session.as_mut(), &probe, &out_ev, &mut players_failing);
+                if last_health != Some(health) {
+                    last_health = Some(health);
+                    out_ev
+                        .send(Event::new(EventKind::HealthChanged(health)))
+                        .unwrap();
+                }
+                // Some servers never log that they are done, so being able to
+                // answer the status also means the start up has finished.
+                if health == Health::Healthy {
+                    lifecycle.transition_if(State::is_starting, State::Online);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, UdpSocket};
+    use std::thread;
+
+    fn read_packet(stream: &mut TcpStream) -> Vec<u8> {
+        // Packets in the test are shorter than 128 bytes, so that their
+        // length takes a single byte.
+        let mut len = [0; 1];
+        stream.read_exact(&mut len).unwrap();
+        let mut body = vec![0; len[0] as usize];
+        stream.read_exact(&mut body).unwrap();
+        body
+    }
+
+    /// Answers a Server List Ping once, returning the port.
+    fn serve_status() -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_packet(&mut stream);
+            read_packet(&mut stream);
+            let json = r#"{"version":{"name":"1.20.1","protocol":763},"players":{"max":20,"online":1,"sample":[{"name":"foo"}]}}"#;
+            let mut packet = vec![json.len() as u8 + 2, 0x00, json.len() as u8];
+            packet.extend_from_slice(json.as_bytes());
+            stream.write_all(&packet).unwrap();
+
+            let ping = read_packet(&mut stream);
+            stream.write_all(&[ping.len() as u8]).unwrap();
+            stream.write_all(&ping).unwrap();
+        });
+        port
+    }
+
+    /// Answers RCON requests over one connection, taking `delay` to run
+    /// commands. Returns the port.
+    fn serve_rcon(delay: Duration) -> u16 {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut header = [0; 12];
+            while stream.read_exact(&mut header).is_ok() {
+                let len = i32::from_le_bytes(header[..4].try_into().unwrap());
+                let mut body = vec![0; len as usize - 8];
+                stream.read_exact(&mut body).unwrap();
+                let (packet_type, payload) = match header[8] {
+                    3 => (2i32, ""),
+                    2 => {
+                        thread::sleep(delay);
+                        (0, "Saved the game")
+                    }
+                    _ => (0, "Unknown request 64"),
+                };
+
+                let mut packet = (payload.len() as i32 + 10).to_le_bytes().to_vec();
+                packet.extend_from_slice(&header[4..8]);
+                packet.extend_from_slice(&packet_type.to_le_bytes());
+                packet.extend_from_slice(payload.as_bytes());
+                packet.extend_from_slice(&[0, 0]);
+                if stream.write_all(&packet).is_err() {
+                    return;
+                }
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn test_execute_read_timeout() {
+        let timeouts = Timeouts {
+            connect: Some(Duration::from_secs(1)),
+            read: Some(Duration::from_millis(100)),
+            write: Some(Duration::from_secs(1)),
+        };
+        let port = serve_rcon(Duration::from_millis(300));
+        let mut session = Session::new("127.0.0.1", port, Password::default(), timeouts);
+
+        let response = session.execute("save-all flush", Some(Duration::from_secs(3)));
+        assert_eq!("Saved the game", response.unwrap());
+        // The longer timeout applies to that command only.
+        assert!(matches!(
+            session.execute("save-all flush", None),
+            Err(SessionError::Rcon(rcon::Error::Timeout))
+        ));
+    }
+
+    #[test]
+    fn test_health_check_without_players() {
+        // Nothing listens on the port, so Query fails.
+        let query_port = UdpSocket::bind(("127.0.0.1", 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let timeouts = Timeouts {
+            connect: Some(Duration::from_secs(1)),
+            read: Some(Duration::from_secs(1)),
+            write: Some(Duration::from_secs(1)),
+        };
+        let (out_ev, events) = channel::unbounded();
+        let mut players_failing = false;
+
+        let probe = StatusProbe::new("127.0.0.1", serve_status(), Some(query_port), timeouts);
+        let health = do_health_check(None, &probe, &out_ev, &mut players_failing);
+        assert_eq!(Health::Healthy, health);
+        assert!(players_failing);
+        assert!(matches!(
+            events.try_recv().unwrap().kind,
+            EventKind::PlayerListFailed(_)
+        ));
+        assert!(matches!(
+            events.try_recv().unwrap().kind,
+            EventKind::Status(status) if status.players == vec!["foo"]
+        ));
+
+        // The failure is reported only once.
+        let probe = StatusProbe::new("127.0.0.1", serve_status(), Some(query_port), timeouts);
+        do_health_check(None, &probe, &out_ev, &mut players_failing);
+        assert!(matches!(
+            events.try_recv().unwrap().kind,
+            EventKind::Status(_)
+        ));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(3));
+        let now = Instant::now();
+        assert!(!backoff.is_waiting(now));
+
+        backoff.fail(now);
+        assert!(backoff.is_waiting(now));
+        assert!(!backoff.is_waiting(now + Duration::from_secs(1)));
+
+        backoff.fail(now);
+        assert!(backoff.is_waiting(now + Duration::from_secs(1)));
+        assert!(!backoff.is_waiting(now + Duration::from_secs(2)));
+
+        backoff.fail(now);
+        backoff.fail(now);
+        assert!(!backoff.is_waiting(now + Duration::from_secs(3)));
+
+        backoff.reset();
+        assert!(!backoff.is_waiting(now));
+    }
+}
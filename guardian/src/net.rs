@@ -0,0 +1,73 @@
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Deadlines for each operation on the connection. `None` waits forever.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+    pub write: Option<Duration>,
+}
+
+/// Returns whether an error is caused by a timeout of the socket.
+pub fn is_timeout(err: &io::Error) -> bool {
+    // Read and write timeouts of a socket are reported as WouldBlock on Unix
+    // and TimedOut on Windows.
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Connects to the first address `addr` resolves to which accepts the
+/// connection, applying the timeouts to the stream.
+pub fn connect_tcp<A>(addr: A, timeouts: Timeouts) -> io::Result<TcpStream>
+where
+    A: ToSocketAddrs,
+{
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        let result = match timeouts.connect {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match result {
+            Ok(stream) => {
+                stream.set_read_timeout(timeouts.read)?;
+                stream.set_write_timeout(timeouts.write)?;
+                return Ok(stream);
+            }
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any addresses",
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_connect_tcp() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let timeouts = Timeouts {
+            read: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let stream = connect_tcp(listener.local_addr().unwrap(), timeouts).unwrap();
+        assert_eq!(timeouts.read, stream.read_timeout().unwrap());
+
+        let err = connect_tcp(&[][..] as &[std::net::SocketAddr], timeouts).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        assert!(is_timeout(&io::ErrorKind::WouldBlock.into()));
+        assert!(!is_timeout(&io::ErrorKind::ConnectionRefused.into()));
+    }
+}
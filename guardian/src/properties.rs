@@ -0,0 +1,219 @@
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    Entry {
+        key: String,
+        value: String,
+        /// The line as read, kept to write back entries left unchanged.
+        raw: Option<String>,
+    },
+    /// Comment or blank line.
+    Other(String),
+}
+
+/// Contents of a Java properties file like `server.properties`, keeping
+/// comments and the order of keys.
+///
+/// Lines continued with a trailing backslash are not supported, since the
+/// server never writes them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Properties {
+    lines: Vec<Line>,
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('f') => result.push('\x0c'),
+            Some('u') => {
+                let code: String = chars.by_ref().take(4).collect();
+                match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    Some(c) => result.push(c),
+                    None => result.push_str(&code),
+                }
+            }
+            Some(c) => result.push(c),
+            None => (),
+        }
+    }
+    result
+}
+
+fn escape(s: &str, is_key: bool) -> String {
+    let mut result = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\x0c' => result.push_str("\\f"),
+            '=' | ':' | '#' | '!' => {
+                result.push('\\');
+                result.push(c);
+            }
+            ' ' if is_key || i == 0 => result.push_str("\\ "),
+            c if c.is_ascii() && !c.is_ascii_control() => result.push(c),
+            c => {
+                let mut buf = [0; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    write!(result, "\\u{:04X}", unit).unwrap();
+                }
+            }
+        }
+    }
+    result
+}
+
+fn parse_entry(line: &str) -> (String, String) {
+    let mut escaped = false;
+    let mut key_end = line.len();
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '=' || c == ':' || c.is_whitespace() {
+            key_end = i;
+            break;
+        }
+    }
+
+    let (key, rest) = line.split_at(key_end);
+    let rest = rest.trim_start();
+    let rest = rest
+        .strip_prefix('=')
+        .or_else(|| rest.strip_prefix(':'))
+        .unwrap_or(rest)
+        .trim_start();
+    (unescape(key), unescape(rest))
+}
+
+impl Properties {
+    pub fn parse(text: &str) -> Self {
+        let lines = text
+            .lines()
+            .map(|line| {
+                let trimmed = line.trim_start();
+                if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+                    Line::Other(line.to_string())
+                } else {
+                    let (key, value) = parse_entry(trimmed);
+                    Line::Entry {
+                        key,
+                        value,
+                        raw: Some(line.to_string()),
+                    }
+                }
+            })
+            .collect();
+        Self { lines }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().rev().find_map(|line| match line {
+            Line::Entry { key: k, value, .. } if k == key => Some(value.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Sets `key` to `value`, replacing the existing entry in place.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let mut found = false;
+        for line in &mut self.lines {
+            if let Line::Entry {
+                key: k,
+                value: v,
+                raw,
+            } = line
+            {
+                if k == key {
+                    if v != value {
+                        *v = value.to_string();
+                        *raw = None;
+                    }
+                    found = true;
+                }
+            }
+        }
+        if !found {
+            self.lines.push(Line::Entry {
+                key: key.to_string(),
+                value: value.to_string(),
+                raw: None,
+            });
+        }
+    }
+}
+
+impl fmt::Display for Properties {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            match line {
+                Line::Entry { raw: Some(raw), .. } | Line::Other(raw) => writeln!(f, "{}", raw)?,
+                Line::Entry { key, value, .. } => {
+                    writeln!(f, "{}={}", escape(key, true), escape(value, false))?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let props = Properties::parse(
+            "#Minecraft server properties\n\
+             level-type=minecraft\\:normal\n\
+             motd = A Minecraft Server\n\
+             key\\ with\\ spaces:value\n\
+             rcon.password=\n\
+             unicode=\\u3042\n",
+        );
+
+        assert_eq!(Some("minecraft:normal"), props.get("level-type"));
+        assert_eq!(Some("A Minecraft Server"), props.get("motd"));
+        assert_eq!(Some("value"), props.get("key with spaces"));
+        assert_eq!(Some(""), props.get("rcon.password"));
+        assert_eq!(Some("\u{3042}"), props.get("unicode"));
+        assert_eq!(None, props.get("#Minecraft"));
+    }
+
+    #[test]
+    fn test_set() {
+        let mut props = Properties::parse(
+            "#Minecraft server properties\n\
+             level-type=minecraft\\:normal\n\
+             enable-rcon=false\n",
+        );
+        props.set("enable-rcon", "true");
+        props.set("level-type", "minecraft:normal");
+        props.set("motd", " Hello: \u{3042}");
+
+        assert_eq!(
+            "#Minecraft server properties\n\
+             level-type=minecraft\\:normal\n\
+             enable-rcon=true\n\
+             motd=\\ Hello\\: \\u3042\n",
+            props.to_string()
+        );
+        assert_eq!(
+            Some(" Hello: \u{3042}"),
+            Properties::parse(&props.to_string()).get("motd")
+        );
+    }
+}
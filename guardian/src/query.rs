@@ -0,0 +1,385 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use crate::net::{self, Timeouts};
+
+#[derive(Debug)]
+pub enum Error {
+    Transport(io::Error),
+    Proto(String),
+    Timeout,
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Self::Transport(ref err) => Some(err),
+            Self::Proto(_) | Self::Timeout => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(_) => write!(f, "Failed to communicate with server"),
+            Self::Proto(message) => write!(f, "Protocol error: {}", message),
+            Self::Timeout => write!(f, "Timed out"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if net::is_timeout(&err) {
+            Self::Timeout
+        } else {
+            Self::Transport(err)
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+const MAGIC: [u8; 2] = [0xfe, 0xfd];
+
+const PACKET_TYPE_HANDSHAKE: u8 = 9;
+const PACKET_TYPE_STAT: u8 = 0;
+
+/// The server ignores the upper 4 bits of each byte of the session ID.
+const SESSION_ID: i32 = 0x0102_0304;
+
+/// Padding sent back by the server before the key-value section of a full
+/// stat, and before the player list.
+const FULL_STAT_KV_PADDING: &[u8] = b"splitnum\x00\x80\x00";
+const FULL_STAT_PLAYERS_PADDING: &[u8] = b"\x01player_\x00\x00";
+
+const MAX_RESPONSE_LEN: usize = 65536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicStat {
+    pub motd: String,
+    pub game_type: String,
+    pub map: String,
+    pub num_players: u32,
+    pub max_players: u32,
+    pub host_port: u16,
+    pub host_ip: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FullStat {
+    pub motd: String,
+    pub game_type: String,
+    pub game_id: String,
+    pub version: String,
+    /// Server software such as "CraftBukkit on Bukkit 1.20.1", which is
+    /// empty for vanilla servers.
+    pub server_mod: String,
+    pub plugins: Vec<String>,
+    pub map: String,
+    pub num_players: u32,
+    pub max_players: u32,
+    pub host_port: u16,
+    pub host_ip: String,
+    pub players: Vec<String>,
+}
+
+/// Reads NUL-terminated strings, which are encoded in ISO-8859-1.
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn expect(&mut self, bytes: &[u8]) -> Result<()> {
+        match self.buf.strip_prefix(bytes) {
+            Some(rest) => {
+                self.buf = rest;
+                Ok(())
+            }
+            None => Err(Error::Proto("Unexpected bytes in response".to_string())),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<String> {
+        let end = match self.buf.iter().position(|&b| b == 0) {
+            Some(end) => end,
+            None => return Err(Error::Proto("String is not terminated".to_string())),
+        };
+
+        let value = self.buf[..end].iter().map(|&b| b as char).collect();
+        self.buf = &self.buf[end + 1..];
+        Ok(value)
+    }
+
+    fn read_u16_le(&mut self) -> Result<u16> {
+        if self.buf.len() < 2 {
+            return Err(Error::Proto("Response is too short".to_string()));
+        }
+
+        let value = u16::from_le_bytes([self.buf[0], self.buf[1]]);
+        self.buf = &self.buf[2..];
+        Ok(value)
+    }
+
+    /// Reads the header which every response starts with.
+    fn read_header(&mut self, packet_type: u8) -> Result<()> {
+        self.expect(&[packet_type])?;
+        self.expect(&SESSION_ID.to_be_bytes())
+    }
+}
+
+fn parse_number<T>(key: &str, value: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+{
+    match value.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => Err(Error::Proto(format!("Invalid {}: {:?}", key, value))),
+    }
+}
+
+fn parse_handshake(buf: &[u8]) -> Result<i32> {
+    let mut reader = Reader::new(buf);
+    reader.read_header(PACKET_TYPE_HANDSHAKE)?;
+    parse_number("challenge token", &reader.read_string()?)
+}
+
+fn parse_basic_stat(buf: &[u8]) -> Result<BasicStat> {
+    let mut reader = Reader::new(buf);
+    reader.read_header(PACKET_TYPE_STAT)?;
+
+    Ok(BasicStat {
+        motd: reader.read_string()?,
+        game_type: reader.read_string()?,
+        map: reader.read_string()?,
+        num_players: parse_number("numplayers", &reader.read_string()?)?,
+        max_players: parse_number("maxplayers", &reader.read_string()?)?,
+        host_port: reader.read_u16_le()?,
+        host_ip: reader.read_string()?,
+    })
+}
+
+/// Splits "CraftBukkit on Bukkit 1.20.1: WorldEdit 7.2; Essentials 2.20"
+/// into the server software and the plugins.
+fn parse_plugins(plugins: &str) -> (String, Vec<String>) {
+    match plugins.split_once(": ") {
+        Some((server_mod, plugins)) => (
+            server_mod.to_string(),
+            plugins.split("; ").map(str::to_string).collect(),
+        ),
+        None => (plugins.to_string(), Vec::new()),
+    }
+}
+
+fn parse_full_stat(buf: &[u8]) -> Result<FullStat> {
+    let mut reader = Reader::new(buf);
+    reader.read_header(PACKET_TYPE_STAT)?;
+    reader.expect(FULL_STAT_KV_PADDING)?;
+
+    let mut stat = FullStat {
+        motd: String::new(),
+        game_type: String::new(),
+        game_id: String::new(),
+        version: String::new(),
+        server_mod: String::new(),
+        plugins: Vec::new(),
+        map: String::new(),
+        num_players: 0,
+        max_players: 0,
+        host_port: 0,
+        host_ip: String::new(),
+        players: Vec::new(),
+    };
+    loop {
+        let key = reader.read_string()?;
+        if key.is_empty() {
+            break;
+        }
+        let value = reader.read_string()?;
+
+        match key.as_str() {
+            "hostname" => stat.motd = value,
+            "gametype" => stat.game_type = value,
+            "game_id" => stat.game_id = value,
+            "version" => stat.version = value,
+            "plugins" => (stat.server_mod, stat.plugins) = parse_plugins(&value),
+            "map" => stat.map = value,
+            "numplayers" => stat.num_players = parse_number(&key, &value)?,
+            "maxplayers" => stat.max_players = parse_number(&key, &value)?,
+            "hostport" => stat.host_port = parse_number(&key, &value)?,
+            "hostip" => stat.host_ip = value,
+            _ => (),
+        }
+    }
+
+    reader.expect(FULL_STAT_PLAYERS_PADDING)?;
+    loop {
+        let player = reader.read_string()?;
+        if player.is_empty() {
+            break;
+        }
+        stat.players.push(player);
+    }
+
+    Ok(stat)
+}
+
+/// Client for the GameSpy4 Query protocol, which is enabled by
+/// `enable-query` in server.properties.
+#[derive(Debug)]
+pub struct QueryClient {
+    socket: UdpSocket,
+}
+
+impl QueryClient {
+    /// Sets up a socket for the server. Since the protocol runs over UDP, the
+    /// connect timeout is not used.
+    pub fn connect<A>(addr: A, timeouts: Timeouts) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(addr)?;
+        socket.set_read_timeout(timeouts.read)?;
+        socket.set_write_timeout(timeouts.write)?;
+
+        Ok(Self { socket })
+    }
+
+    fn request(&mut self, packet_type: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&MAGIC);
+        buf.push(packet_type);
+        buf.extend_from_slice(&SESSION_ID.to_be_bytes());
+        buf.extend_from_slice(payload);
+        self.socket.send(&buf)?;
+
+        let mut response = vec![0; MAX_RESPONSE_LEN];
+        let len = self.socket.recv(&mut response)?;
+        response.truncate(len);
+        Ok(response)
+    }
+
+    /// Obtains a challenge token, which expires every 30 seconds.
+    fn handshake(&mut self) -> Result<i32> {
+        let response = self.request(PACKET_TYPE_HANDSHAKE, &[])?;
+        parse_handshake(&response)
+    }
+
+    pub fn basic_stat(&mut self) -> Result<BasicStat> {
+        let token = self.handshake()?;
+        let response = self.request(PACKET_TYPE_STAT, &token.to_be_bytes())?;
+        parse_basic_stat(&response)
+    }
+
+    pub fn full_stat(&mut self) -> Result<FullStat> {
+        let token = self.handshake()?;
+
+        let mut payload = token.to_be_bytes().to_vec();
+        payload.extend_from_slice(&[0; 4]);
+        let response = self.request(PACKET_TYPE_STAT, &payload)?;
+        parse_full_stat(&response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_handshake() {
+        assert_eq!(
+            9513307,
+            parse_handshake(b"\x09\x01\x02\x03\x049513307\x00").unwrap()
+        );
+        assert_eq!(-1, parse_handshake(b"\x09\x01\x02\x03\x04-1\x00").unwrap());
+        assert!(matches!(
+            parse_handshake(b"\x09\x05\x06\x07\x089513307\x00"),
+            Err(Error::Proto(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_basic_stat() {
+        assert_eq!(
+            BasicStat {
+                motd: "A Minecraft Server".to_string(),
+                game_type: "SMP".to_string(),
+                map: "world".to_string(),
+                num_players: 2,
+                max_players: 20,
+                host_port: 25565,
+                host_ip: "127.0.0.1".to_string(),
+            },
+            parse_basic_stat(
+                b"\x00\x01\x02\x03\x04A Minecraft Server\x00SMP\x00world\x002\x0020\x00\xdd\x63127.0.0.1\x00"
+            )
+            .unwrap()
+        );
+        assert!(parse_basic_stat(b"\x00\x01\x02\x03\x04A Minecraft Server\x00SMP").is_err());
+    }
+
+    #[test]
+    fn test_parse_full_stat() {
+        let stat = parse_full_stat(
+            b"\x00\x01\x02\x03\x04splitnum\x00\x80\x00\
+              hostname\x00A Minecraft Server\x00gametype\x00SMP\x00game_id\x00MINECRAFT\x00\
+              version\x001.20.1\x00plugins\x00CraftBukkit on Bukkit 1.20.1: WorldEdit 7.2; Essentials 2.20\x00\
+              map\x00world\x00numplayers\x002\x00maxplayers\x0020\x00hostport\x0025565\x00\
+              hostip\x00127.0.0.1\x00\x00\
+              \x01player_\x00\x00foo\x00bar\x00\x00",
+        )
+        .unwrap();
+
+        assert_eq!("A Minecraft Server", stat.motd);
+        assert_eq!("1.20.1", stat.version);
+        assert_eq!("CraftBukkit on Bukkit 1.20.1", stat.server_mod);
+        assert_eq!(vec!["WorldEdit 7.2", "Essentials 2.20"], stat.plugins);
+        assert_eq!(2, stat.num_players);
+        assert_eq!(20, stat.max_players);
+        assert_eq!(25565, stat.host_port);
+        assert_eq!(vec!["foo", "bar"], stat.players);
+    }
+
+    #[test]
+    fn test_full_stat_over_udp() {
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let addr = server.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0; 64];
+
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(b"\xfe\xfd\x09\x01\x02\x03\x04", &buf[..len]);
+            server
+                .send_to(b"\x09\x01\x02\x03\x04123\x00", peer)
+                .unwrap();
+
+            let (len, peer) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(
+                b"\xfe\xfd\x00\x01\x02\x03\x04\x00\x00\x00\x7b\x00\x00\x00\x00",
+                &buf[..len]
+            );
+            server
+                .send_to(
+                    b"\x00\x01\x02\x03\x04splitnum\x00\x80\x00numplayers\x001\x00\x00\
+                      \x01player_\x00\x00foo\x00\x00",
+                    peer,
+                )
+                .unwrap();
+        });
+
+        let mut client = QueryClient::connect(addr, Timeouts::default()).unwrap();
+        let stat = client.full_stat().unwrap();
+        assert_eq!(1, stat.num_players);
+        assert_eq!(vec!["foo"], stat.players);
+
+        handle.join().unwrap();
+    }
+}
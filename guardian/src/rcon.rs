@@ -1,20 +1,45 @@
 use std::error;
 use std::fmt;
 use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::command::Command;
+use crate::net;
+
+pub use crate::net::Timeouts;
+
+pub mod asynchronous;
 
 #[derive(Debug)]
 pub enum Error {
     Transport(io::Error),
+    /// Failed to write a request before any of it was sent, so it can be
+    /// sent again safely.
+    NotSent(io::Error),
     Upstream(String),
     RequestTooLong,
-    Proto,
+    Proto(String),
+    IdMismatch {
+        expected: i32,
+        actual: i32,
+    },
+    Timeout,
+    InvalidArgument(String),
+    UnexpectedResponse(String),
 }
 
 impl error::Error for Error {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match *self {
-            Self::Transport(ref err) => Some(err),
-            Self::Upstream(_) | Self::RequestTooLong | Self::Proto => None,
+            Self::Transport(ref err) | Self::NotSent(ref err) => Some(err),
+            Self::Upstream(_)
+            | Self::RequestTooLong
+            | Self::Proto(_)
+            | Self::IdMismatch { .. }
+            | Self::Timeout
+            | Self::InvalidArgument(_)
+            | Self::UnexpectedResponse(_) => None,
         }
     }
 }
@@ -25,13 +50,55 @@ impl fmt::Display for Error {
             Self::Upstream(message) => write!(f, "Error from upstream: {}", message),
             Self::RequestTooLong => write!(f, "Request is too long"),
             Self::Transport(_) => write!(f, "Failed to write to stream"),
-            Self::Proto => write!(f, "Protocol error"),
+            Self::NotSent(_) => write!(f, "Failed to send request"),
+            Self::Proto(message) => write!(f, "Protocol error: {}", message),
+            Self::IdMismatch { expected, actual } => write!(
+                f,
+                "Response for request {} received while waiting for {}",
+                actual, expected
+            ),
+            Self::Timeout => write!(f, "Timed out"),
+            Self::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            Self::UnexpectedResponse(response) => {
+                write!(f, "Unexpected response: {}", response)
+            }
         }
     }
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if net::is_timeout(&err) {
+            Self::Timeout
+        } else {
+            Self::Transport(err)
+        }
+    }
+}
+
+const PACKET_TYPE_RESPONSE: i32 = 0;
+const PACKET_TYPE_COMMAND: i32 = 2;
+const PACKET_TYPE_AUTH_RESPONSE: i32 = 2;
+const PACKET_TYPE_AUTH: i32 = 3;
+const PACKET_TYPE_INVALID: i32 = 100;
+
+const HEADER_LEN: usize = 12;
+
+/// Length of a packet without the length field and the payload.
+const MIN_PACKET_LEN: usize = 4 + 4 + 2;
+
+/// The server limits the length of requests to 1460 bytes including the
+/// length field.
+const MAX_REQUEST_LEN: usize = 1456;
+
+/// Longest command line which fits in a request.
+pub const MAX_COMMAND_LEN: usize = MAX_REQUEST_LEN - MIN_PACKET_LEN;
+
+/// The server splits a response into fragments of 4096 bytes.
+const MAX_RESPONSE_LEN: usize = MIN_PACKET_LEN + 4096;
+
 #[derive(Debug)]
 pub struct RconClient<T>
 where
@@ -39,6 +106,7 @@ where
 {
     transport: T,
     handle_long_resp: bool,
+    req_ids: ReqIdAllocator,
 }
 
 #[derive(Debug)]
@@ -48,6 +116,185 @@ struct RconPacket {
     payload: Vec<u8>,
 }
 
+impl RconPacket {
+    fn encode(req_id: i32, packet_type: i32, payload: &str) -> Result<Vec<u8>> {
+        let len = MIN_PACKET_LEN + payload.len();
+        if len > MAX_REQUEST_LEN {
+            return Err(Error::RequestTooLong);
+        }
+
+        let mut buf = Vec::<u8>::with_capacity(4 + len);
+
+        buf.extend_from_slice(&(len as i32).to_le_bytes());
+        buf.extend_from_slice(&req_id.to_le_bytes());
+        buf.extend_from_slice(&packet_type.to_le_bytes());
+        buf.extend_from_slice(payload.as_bytes());
+        buf.extend_from_slice(&[0; 2]);
+
+        Ok(buf)
+    }
+
+    /// Parses the fixed-size header and returns the packet with its payload
+    /// left empty, along with the number of bytes remaining in the packet.
+    fn decode_header(buf: &[u8; HEADER_LEN]) -> Result<(Self, usize)> {
+        let len = i32::from_le_bytes(buf[0..4].try_into().unwrap());
+        let len = match usize::try_from(len) {
+            Ok(len) if (MIN_PACKET_LEN..=MAX_RESPONSE_LEN).contains(&len) => len,
+            _ => {
+                return Err(Error::Proto(format!(
+                    "Packet length {} is out of range ({}..={})",
+                    len, MIN_PACKET_LEN, MAX_RESPONSE_LEN
+                )))
+            }
+        };
+
+        let req_id = i32::from_le_bytes(buf[4..8].try_into().unwrap());
+        let packet_type = i32::from_le_bytes(buf[8..12].try_into().unwrap());
+
+        let packet = Self {
+            req_id,
+            packet_type,
+            payload: Vec::new(),
+        };
+        Ok((packet, len - 4 - 4))
+    }
+
+    /// Sets the payload from the rest of the packet, which is terminated by
+    /// two NUL bytes.
+    fn set_body(&mut self, mut body: Vec<u8>) -> Result<()> {
+        if !body.ends_with(&[0, 0]) {
+            return Err(Error::Proto(
+                "Packet is not terminated by two NUL bytes".to_string(),
+            ));
+        }
+
+        body.truncate(body.len() - 2);
+        self.payload = body;
+        Ok(())
+    }
+
+    fn check_auth_response(&self, req_id: i32) -> Result<()> {
+        if self.req_id == -1 || self.packet_type != PACKET_TYPE_AUTH_RESPONSE {
+            Err(Error::Upstream(
+                String::from_utf8_lossy(&self.payload).into_owned(),
+            ))
+        } else if self.req_id != req_id {
+            Err(Error::IdMismatch {
+                expected: req_id,
+                actual: self.req_id,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ReqIdAllocator {
+    next: i32,
+}
+
+impl ReqIdAllocator {
+    fn new() -> Self {
+        Self { next: 1 }
+    }
+
+    /// Allocates a request ID for the next packet.
+    ///
+    /// IDs are kept positive since the server uses -1 to report an
+    /// authentication failure.
+    fn allocate(&mut self) -> i32 {
+        let req_id = self.next;
+        self.next = self.next.checked_add(1).unwrap_or(1);
+        req_id
+    }
+}
+
+/// A command which has been sent but whose response is not read yet.
+#[derive(Debug)]
+struct PendingRequest {
+    req_id: i32,
+    sentinel_id: Option<i32>,
+}
+
+impl PendingRequest {
+    /// Appends the payload of a received packet to the response and returns
+    /// whether the response is complete.
+    ///
+    /// The server fragments responses longer than 4096 bytes without marking
+    /// the last fragment, so in long response mode an invalid packet is sent
+    /// right after the command and fragments are collected until the reply to
+    /// it arrives. Vanilla servers answer with "Unknown request 64" while
+    /// others echo an empty response, so the reply is identified by its
+    /// request ID only.
+    fn accept(&self, packet: RconPacket, response: &mut Vec<u8>) -> Result<bool> {
+        if Some(packet.req_id) == self.sentinel_id {
+            return Ok(true);
+        } else if packet.req_id != self.req_id {
+            return Err(Error::IdMismatch {
+                expected: self.req_id,
+                actual: packet.req_id,
+            });
+        } else if self.sentinel_id.is_some() && packet.packet_type != PACKET_TYPE_RESPONSE {
+            return Err(Error::Proto(format!(
+                "Unexpected packet type {} in response",
+                packet.packet_type
+            )));
+        }
+
+        response.extend_from_slice(&packet.payload);
+
+        Ok(self.sentinel_id.is_none())
+    }
+}
+
+/// Reports a failure to send a packet following one already sent.
+fn already_sent(err: Error) -> Error {
+    match err {
+        Error::NotSent(err) => Error::Transport(err),
+        err => err,
+    }
+}
+
+/// Decodes a response once all the fragments are received.
+///
+/// Fragments are concatenated before decoding since they are split at byte
+/// boundaries, which may fall in the middle of a multi-byte character.
+fn decode_response(response: Vec<u8>) -> String {
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+impl RconClient<TcpStream> {
+    /// Connects to the server, applying the timeouts to the socket.
+    pub fn connect<A>(addr: A, timeouts: Timeouts, handle_long_resp: bool) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let transport = net::connect_tcp(addr, timeouts)?;
+        Ok(Self::new(transport, handle_long_resp))
+    }
+
+    /// Checks without blocking whether the connection is still usable. The
+    /// server sends nothing between requests, so anything to read means that
+    /// the connection was closed or is out of sync.
+    pub fn is_closed(&self) -> bool {
+        if self.transport.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let mut buf = [0; 1];
+        let result = self.transport.peek(&mut buf);
+        if self.transport.set_nonblocking(false).is_err() {
+            return true;
+        }
+        !matches!(result, Err(err) if err.kind() == io::ErrorKind::WouldBlock)
+    }
+
+    /// Changes how long to wait for responses. `None` waits forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.transport.set_read_timeout(timeout)?)
+    }
+}
+
 impl<T> RconClient<T>
 where
     T: Read + Write,
@@ -56,128 +303,117 @@ where
         Self {
             transport,
             handle_long_resp,
+            req_ids: ReqIdAllocator::new(),
         }
     }
 
-    fn send_packet(&mut self, packet_type: i32, payload: &str) -> Result<()> {
-        let len = 4 + 4 + 2 + payload.len();
-        if len > 1456 {
-            return Err(Error::RequestTooLong);
-        }
-
-        let mut buf = Vec::<u8>::new();
-
-        buf.write_all(&(len as i32).to_le_bytes()).unwrap();
-        buf.write_all(&1i32.to_le_bytes()).unwrap();
-        buf.write_all(&packet_type.to_le_bytes()).unwrap();
-        buf.write_all(payload.as_bytes()).unwrap();
-        buf.write_all(&[0; 2]).unwrap();
-
-        if let Err(err) = self.transport.write_all(buf.as_slice()) {
-            return Err(Error::Transport(err));
+    fn send_packet(&mut self, req_id: i32, packet_type: i32, payload: &str) -> Result<()> {
+        let buf = RconPacket::encode(req_id, packet_type, payload)?;
+
+        // Failing before the first byte is written is told apart, since the
+        // server cannot have seen the packet then.
+        let written = loop {
+            match self.transport.write(&buf) {
+                Ok(0) => return Err(Error::NotSent(io::ErrorKind::WriteZero.into())),
+                Ok(written) => break written,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(Error::NotSent(err)),
+            }
         };
+        self.transport.write_all(&buf[written..])?;
 
         Ok(())
     }
 
     fn recv_packet(&mut self) -> Result<RconPacket> {
-        let mut buf = [0; 12];
-        match self.transport.read_exact(&mut buf) {
-            Ok(_) => (),
-            Err(err) => return Err(Error::Transport(err)),
-        }
+        let mut buf = [0; HEADER_LEN];
+        self.transport.read_exact(&mut buf)?;
 
-        let len = i32::from_le_bytes(buf[0..4].try_into().unwrap());
-        if len < 10 {
-            return Err(Error::Proto);
-        }
+        let (mut packet, body_len) = RconPacket::decode_header(&buf)?;
 
-        let req_id = i32::from_le_bytes(buf[4..8].try_into().unwrap());
-        let packet_type = i32::from_le_bytes(buf[8..12].try_into().unwrap());
+        let mut body = vec![0; body_len];
+        self.transport.read_exact(body.as_mut_slice())?;
+        packet.set_body(body)?;
 
-        let mut payload = vec![0; len as usize - 4 - 4];
-        match self.transport.read_exact(payload.as_mut_slice()) {
-            Ok(_) => (),
-            Err(err) => return Err(Error::Transport(err)),
-        }
-        let payload = payload[0..payload.len() - 2].to_vec();
+        Ok(packet)
+    }
+
+    fn send_command(&mut self, cmd: &str) -> Result<PendingRequest> {
+        let req_id = self.req_ids.allocate();
+        self.send_packet(req_id, PACKET_TYPE_COMMAND, cmd)?;
+
+        let sentinel_id = if self.handle_long_resp {
+            let sentinel_id = self.req_ids.allocate();
+            self.send_packet(sentinel_id, PACKET_TYPE_INVALID, "")
+                .map_err(already_sent)?;
+            Some(sentinel_id)
+        } else {
+            None
+        };
 
-        Ok(RconPacket {
+        Ok(PendingRequest {
             req_id,
-            packet_type,
-            payload,
+            sentinel_id,
         })
     }
 
-    fn recv_string(&mut self) -> Result<String> {
-        match self.send_packet(100, "") {
-            Ok(_) => (),
-            Err(err) => return Err(err),
-        }
-
-        let mut result = String::new();
+    fn recv_response(&mut self, request: &PendingRequest) -> Result<String> {
+        let mut response = Vec::new();
         loop {
-            let packet = match self.recv_packet() {
-                Ok(packet) => packet,
-                Err(err) => return Err(err),
-            };
-
-            if packet.packet_type != 0 {
-                return Err(Error::Proto);
-            }
-
-            let payload = String::from_utf8_lossy(&packet.payload);
-            if payload == "Unknown request 64" {
+            let packet = self.recv_packet()?;
+            if request.accept(packet, &mut response)? {
                 break;
             }
-
-            result += &payload;
-
-            break;
         }
 
-        Ok(result)
+        Ok(decode_response(response))
     }
 
     pub fn authenticate(&mut self, passwd: &str) -> Result<()> {
-        match self.send_packet(3, passwd) {
-            Ok(_) => (),
-            Err(err) => return Err(err),
-        }
+        let req_id = self.req_ids.allocate();
+        self.send_packet(req_id, PACKET_TYPE_AUTH, passwd)?;
 
-        let packet = match self.recv_packet() {
-            Ok(packet) => packet,
-            Err(err) => return Err(err),
-        };
-
-        if packet.req_id == -1 || packet.packet_type != 2 {
-            Err(Error::Upstream(
-                String::from_utf8_lossy(&packet.payload).into_owned(),
-            ))
-        } else {
-            Ok(())
-        }
+        self.recv_packet()?.check_auth_response(req_id)
     }
 
     pub fn execute(&mut self, cmd: &str) -> Result<String> {
-        if let Err(err) = self.send_packet(2, cmd) {
-            return Err(err);
-        }
+        let request = self.send_command(cmd)?;
+        self.recv_response(&request)
+    }
 
-        if self.handle_long_resp {
-            self.recv_string()
-        } else {
-            match self.recv_packet() {
-                Ok(packet) => Ok(String::from_utf8_lossy(&packet.payload).into_owned()),
-                Err(err) => Err(err),
-            }
-        }
+    pub fn run<C>(&mut self, cmd: &C) -> Result<C::Output>
+    where
+        C: Command,
+    {
+        let response = self.execute(&cmd.to_command_line())?;
+        cmd.parse_response(&response)
+    }
+
+    /// Sends all the commands at once, then reads their responses in order.
+    ///
+    /// This saves a round trip per command since the server processes
+    /// requests on a connection one by one.
+    pub fn execute_pipelined(&mut self, cmds: &[&str]) -> Result<Vec<String>> {
+        let requests = cmds
+            .iter()
+            .enumerate()
+            .map(|(i, cmd)| match self.send_command(cmd) {
+                Err(err) if i > 0 => Err(already_sent(err)),
+                result => result,
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        requests
+            .iter()
+            .map(|request| self.recv_response(request))
+            .collect()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     struct MockTcpStream {
         readable_buf: &'static [u8],
@@ -206,7 +442,7 @@ mod tests {
                 // auth
                 10, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0,
                 // command
-                14, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', b'a', 0, 0,
+                14, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', b'a', 0, 0,
             ],
             wrote_bytes: vec![],
         };
@@ -224,8 +460,261 @@ mod tests {
                 // auth
                 11, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, b'x', 0, 0,
                 // command
-                14, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, b'h', b'o', b'g', b'e', 0, 0
+                14, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, b'h', b'o', b'g', b'e', 0, 0
+            ]
+        );
+    }
+
+    #[test]
+    fn test_execute_long_response() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                // fragments
+                13, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', 0, 0,
+                11, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'a', 0, 0,
+                // reply to the invalid packet
+                28, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0,
+                b'U', b'n', b'k', b'n', b'o', b'w', b'n', b' ', b'r', b'e', b'q', b'u', b'e', b's', b't', b' ', b'6', b'4', 0, 0,
+            ],
+            wrote_bytes: vec![],
+        };
+
+        {
+            let mut client = RconClient::new(&mut mock_stream, true);
+            assert_eq!("fuga", client.execute("hoge").unwrap());
+        }
+
+        #[rustfmt::skip]
+        assert_eq!(
+            mock_stream.wrote_bytes,
+            [
+                // command
+                14, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, b'h', b'o', b'g', b'e', 0, 0,
+                // invalid packet
+                10, 0, 0, 0, 2, 0, 0, 0, 100, 0, 0, 0, 0, 0,
             ]
         );
     }
+
+    #[test]
+    fn test_execute_long_response_empty_echo() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                14, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', b'a', 0, 0,
+                10, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            wrote_bytes: vec![],
+        };
+
+        let mut client = RconClient::new(&mut mock_stream, true);
+        assert_eq!("fuga", client.execute("hoge").unwrap());
+    }
+
+    #[test]
+    fn test_execute_id_mismatch() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                14, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', b'a', 0, 0,
+            ],
+            wrote_bytes: vec![],
+        };
+
+        let mut client = RconClient::new(&mut mock_stream, false);
+        assert!(matches!(
+            client.execute("hoge"),
+            Err(Error::IdMismatch {
+                expected: 1,
+                actual: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn test_execute_pipelined() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                14, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', b'a', 0, 0,
+                14, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, b'p', b'i', b'y', b'o', 0, 0,
+            ],
+            wrote_bytes: vec![],
+        };
+
+        {
+            let mut client = RconClient::new(&mut mock_stream, false);
+            assert_eq!(
+                vec!["fuga", "piyo"],
+                client.execute_pipelined(&["hoge", "foo"]).unwrap()
+            );
+        }
+
+        #[rustfmt::skip]
+        assert_eq!(
+            mock_stream.wrote_bytes,
+            [
+                14, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, b'h', b'o', b'g', b'e', 0, 0,
+                13, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, b'f', b'o', b'o', 0, 0,
+            ]
+        );
+    }
+
+    struct BrokenStream {
+        /// Number of bytes accepted before writing fails.
+        writable: usize,
+    }
+    impl Read for BrokenStream {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::ConnectionReset.into())
+        }
+    }
+    impl Write for BrokenStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.writable == 0 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let len = buf.len().min(self.writable);
+            self.writable -= len;
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_execute_not_sent() {
+        let mut client = RconClient::new(BrokenStream { writable: 0 }, true);
+        assert!(matches!(client.execute("hoge"), Err(Error::NotSent(_))));
+
+        // Only part of the command was sent.
+        let mut client = RconClient::new(BrokenStream { writable: 4 }, true);
+        assert!(matches!(client.execute("hoge"), Err(Error::Transport(_))));
+
+        // The command was sent but not the invalid packet after it.
+        let mut client = RconClient::new(BrokenStream { writable: 18 }, true);
+        assert!(matches!(client.execute("hoge"), Err(Error::Transport(_))));
+
+        let mut client = RconClient::new(BrokenStream { writable: 100 }, false);
+        assert!(matches!(client.execute("hoge"), Err(Error::Transport(_))));
+        let mut client = RconClient::new(BrokenStream { writable: 18 }, false);
+        assert!(matches!(
+            client.execute_pipelined(&["hoge", "foo"]),
+            Err(Error::Transport(_))
+        ));
+    }
+
+    #[test]
+    fn test_is_closed() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client =
+            RconClient::connect(listener.local_addr().unwrap(), Timeouts::default(), false)
+                .unwrap();
+        let (conn, _) = listener.accept().unwrap();
+        assert!(!client.is_closed());
+
+        drop(conn);
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while !client.is_closed() {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let timeouts = Timeouts {
+            read: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+
+        let mut client =
+            RconClient::connect(listener.local_addr().unwrap(), timeouts, false).unwrap();
+        let _conn = listener.accept().unwrap();
+        assert!(matches!(client.authenticate("x"), Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn test_recv_malformed_packet() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                14, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', b'a', 0, b'a',
+            ],
+            wrote_bytes: vec![],
+        };
+        let mut client = RconClient::new(&mut mock_stream, false);
+        assert!(matches!(client.execute("hoge"), Err(Error::Proto(_))));
+
+        for len in [-1i32, 0, 9, MAX_RESPONSE_LEN as i32 + 1, 0x7fffffff] {
+            let mut buf = [0; HEADER_LEN];
+            buf[0..4].copy_from_slice(&len.to_le_bytes());
+            assert!(matches!(
+                RconPacket::decode_header(&buf),
+                Err(Error::Proto(_))
+            ));
+        }
+    }
+
+    struct FuzzStream {
+        readable_buf: io::Cursor<Vec<u8>>,
+    }
+    impl Read for FuzzStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.readable_buf.read(buf)
+        }
+    }
+    impl Write for FuzzStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_decode_arbitrary_bytes(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+            let mut client = RconClient::new(
+                FuzzStream {
+                    readable_buf: io::Cursor::new(bytes),
+                },
+                true,
+            );
+            let _ = client.execute("hoge");
+        }
+
+        #[test]
+        fn test_decode_arbitrary_header(header in any::<[u8; HEADER_LEN]>()) {
+            if let Ok((_, body_len)) = RconPacket::decode_header(&header) {
+                prop_assert!(body_len >= 2);
+                prop_assert!(body_len <= MAX_RESPONSE_LEN - 4 - 4);
+            }
+        }
+
+        #[test]
+        fn test_decode_encoded_packet(
+            req_id in 1..i32::MAX,
+            payload in "[^\\x00]{0,256}",
+        ) {
+            let buf = RconPacket::encode(req_id, PACKET_TYPE_RESPONSE, &payload).unwrap();
+            let mut client = RconClient::new(
+                FuzzStream {
+                    readable_buf: io::Cursor::new(buf),
+                },
+                false,
+            );
+
+            let packet = client.recv_packet().unwrap();
+            prop_assert_eq!(req_id, packet.req_id);
+            prop_assert_eq!(PACKET_TYPE_RESPONSE, packet.packet_type);
+            prop_assert_eq!(payload.as_bytes(), packet.payload.as_slice());
+        }
+    }
 }
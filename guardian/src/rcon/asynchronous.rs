@@ -0,0 +1,371 @@
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time;
+
+use crate::command::Command;
+
+use super::{
+    already_sent, decode_response, Error, PendingRequest, RconPacket, ReqIdAllocator, Result,
+    Timeouts, HEADER_LEN, PACKET_TYPE_AUTH, PACKET_TYPE_COMMAND, PACKET_TYPE_INVALID,
+};
+
+async fn with_timeout<F, U>(timeout: Option<Duration>, future: F) -> Result<U>
+where
+    F: Future<Output = io::Result<U>>,
+{
+    match timeout {
+        Some(timeout) => match time::timeout(timeout, future).await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err(Error::Timeout),
+        },
+        None => Ok(future.await?),
+    }
+}
+
+/// Async counterpart of [`super::RconClient`] for use on a tokio runtime.
+#[derive(Debug)]
+pub struct RconClient<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    transport: T,
+    handle_long_resp: bool,
+    req_ids: ReqIdAllocator,
+    timeouts: Timeouts,
+}
+
+impl RconClient<TcpStream> {
+    /// Connects to the server. Read and write timeouts are applied to each
+    /// operation on the connection.
+    pub async fn connect<A>(addr: A, timeouts: Timeouts, handle_long_resp: bool) -> Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let transport = with_timeout(timeouts.connect, TcpStream::connect(addr)).await?;
+
+        let mut client = Self::new(transport, handle_long_resp);
+        client.timeouts = timeouts;
+        Ok(client)
+    }
+}
+
+impl<T> RconClient<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(transport: T, handle_long_resp: bool) -> Self {
+        Self {
+            transport,
+            handle_long_resp,
+            req_ids: ReqIdAllocator::new(),
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    async fn send_packet(&mut self, req_id: i32, packet_type: i32, payload: &str) -> Result<()> {
+        let buf = RconPacket::encode(req_id, packet_type, payload)?;
+
+        // Failing before the first byte is written is told apart, since the
+        // server cannot have seen the packet then.
+        let written = match with_timeout(self.timeouts.write, self.transport.write(&buf)).await {
+            Ok(0) => return Err(Error::NotSent(io::ErrorKind::WriteZero.into())),
+            Ok(written) => written,
+            Err(Error::Transport(err)) => return Err(Error::NotSent(err)),
+            Err(Error::Timeout) => return Err(Error::NotSent(io::ErrorKind::TimedOut.into())),
+            Err(err) => return Err(err),
+        };
+        with_timeout(
+            self.timeouts.write,
+            self.transport.write_all(&buf[written..]),
+        )
+        .await
+    }
+
+    async fn recv_packet(&mut self) -> Result<RconPacket> {
+        let mut buf = [0; HEADER_LEN];
+        with_timeout(self.timeouts.read, self.transport.read_exact(&mut buf)).await?;
+
+        let (mut packet, body_len) = RconPacket::decode_header(&buf)?;
+
+        let mut body = vec![0; body_len];
+        with_timeout(
+            self.timeouts.read,
+            self.transport.read_exact(body.as_mut_slice()),
+        )
+        .await?;
+        packet.set_body(body)?;
+
+        Ok(packet)
+    }
+
+    async fn send_command(&mut self, cmd: &str) -> Result<PendingRequest> {
+        let req_id = self.req_ids.allocate();
+        self.send_packet(req_id, PACKET_TYPE_COMMAND, cmd).await?;
+
+        let sentinel_id = if self.handle_long_resp {
+            let sentinel_id = self.req_ids.allocate();
+            self.send_packet(sentinel_id, PACKET_TYPE_INVALID, "")
+                .await
+                .map_err(already_sent)?;
+            Some(sentinel_id)
+        } else {
+            None
+        };
+
+        Ok(PendingRequest {
+            req_id,
+            sentinel_id,
+        })
+    }
+
+    async fn recv_response(&mut self, request: &PendingRequest) -> Result<String> {
+        let mut response = Vec::new();
+        loop {
+            let packet = self.recv_packet().await?;
+            if request.accept(packet, &mut response)? {
+                break;
+            }
+        }
+
+        Ok(decode_response(response))
+    }
+
+    pub async fn authenticate(&mut self, passwd: &str) -> Result<()> {
+        let req_id = self.req_ids.allocate();
+        self.send_packet(req_id, PACKET_TYPE_AUTH, passwd).await?;
+
+        self.recv_packet().await?.check_auth_response(req_id)
+    }
+
+    pub async fn execute(&mut self, cmd: &str) -> Result<String> {
+        let request = self.send_command(cmd).await?;
+        self.recv_response(&request).await
+    }
+
+    pub async fn run<C>(&mut self, cmd: &C) -> Result<C::Output>
+    where
+        C: Command,
+    {
+        let response = self.execute(&cmd.to_command_line()).await?;
+        cmd.parse_response(&response)
+    }
+
+    /// Sends all the commands at once, then reads their responses in order.
+    pub async fn execute_pipelined(&mut self, cmds: &[&str]) -> Result<Vec<String>> {
+        let mut requests = Vec::with_capacity(cmds.len());
+        for (i, cmd) in cmds.iter().enumerate() {
+            let request = match self.send_command(cmd).await {
+                Err(err) if i > 0 => Err(already_sent(err)),
+                result => result,
+            };
+            requests.push(request?);
+        }
+
+        let mut responses = Vec::with_capacity(requests.len());
+        for request in &requests {
+            responses.push(self.recv_response(request).await?);
+        }
+
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+
+    struct MockTcpStream {
+        readable_buf: &'static [u8],
+        wrote_bytes: Vec<u8>,
+    }
+    impl AsyncRead for MockTcpStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.readable_buf).poll_read(cx, buf)
+        }
+    }
+    impl AsyncWrite for MockTcpStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Pin::new(&mut self.wrote_bytes).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    struct BrokenStream {
+        /// Number of bytes accepted before writing fails.
+        writable: usize,
+    }
+    impl AsyncRead for BrokenStream {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            _buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()))
+        }
+    }
+    impl AsyncWrite for BrokenStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if self.writable == 0 {
+                return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+            let len = buf.len().min(self.writable);
+            self.writable -= len;
+            Poll::Ready(Ok(len))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_success() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                // auth
+                10, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0,
+                // command
+                14, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', b'a', 0, 0,
+            ],
+            wrote_bytes: vec![],
+        };
+
+        {
+            let mut client = RconClient::new(&mut mock_stream, false);
+            client.authenticate("x").await.unwrap();
+            assert_eq!("fuga", client.execute("hoge").await.unwrap());
+        }
+
+        #[rustfmt::skip]
+        assert_eq!(
+            mock_stream.wrote_bytes,
+            [
+                // auth
+                11, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, b'x', 0, 0,
+                // command
+                14, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, b'h', b'o', b'g', b'e', 0, 0
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_failure() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                31, 0, 0, 0, 255, 255, 255, 255, 2, 0, 0, 0,
+                b'a', b'u', b't', b'h', b'e', b'n', b't', b'i', b'c', b'a', b't', b'i', b'o', b'n',
+                b' ', b'f', b'a', b'i', b'l', b'e', b'd', 0, 0,
+            ],
+            wrote_bytes: vec![],
+        };
+
+        let mut client = RconClient::new(&mut mock_stream, false);
+        assert!(matches!(
+            client.authenticate("y").await,
+            Err(Error::Upstream(message)) if message == "authentication failed"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_execute_long_response() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                // fragments
+                13, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'f', b'u', b'g', 0, 0,
+                11, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'a', 0, 0,
+                // reply to the invalid packet
+                10, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+            wrote_bytes: vec![],
+        };
+
+        let mut client = RconClient::new(&mut mock_stream, true);
+        assert_eq!("fuga", client.execute("hoge").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_execute_not_sent() {
+        let mut client = RconClient::new(BrokenStream { writable: 0 }, true);
+        assert!(matches!(
+            client.execute("hoge").await,
+            Err(Error::NotSent(_))
+        ));
+
+        // Only part of the command was sent.
+        let mut client = RconClient::new(BrokenStream { writable: 4 }, true);
+        assert!(matches!(
+            client.execute("hoge").await,
+            Err(Error::Transport(_))
+        ));
+
+        // The command was sent but not the invalid packet after it.
+        let mut client = RconClient::new(BrokenStream { writable: 18 }, true);
+        assert!(matches!(
+            client.execute("hoge").await,
+            Err(Error::Transport(_))
+        ));
+
+        let mut client = RconClient::new(BrokenStream { writable: 100 }, false);
+        assert!(matches!(
+            client.execute("hoge").await,
+            Err(Error::Transport(_))
+        ));
+        let mut client = RconClient::new(BrokenStream { writable: 18 }, false);
+        assert!(matches!(
+            client.execute_pipelined(&["hoge", "foo"]).await,
+            Err(Error::Transport(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_timeout() {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let timeouts = Timeouts {
+            read: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+
+        let mut client = RconClient::connect(listener.local_addr().unwrap(), timeouts, false)
+            .await
+            .unwrap();
+        let _conn = listener.accept().await.unwrap();
+        assert!(matches!(
+            client.authenticate("x").await,
+            Err(Error::Timeout)
+        ));
+    }
+}
@@ -0,0 +1,184 @@
+use premises_config::v1::{Restart, RestartPolicy};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+/// How the server process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Exit {
+    Success,
+    Code(i32),
+    /// Killed by a signal, e.g. by the OOM killer.
+    Signal(i32),
+    /// The server could not be launched, or the guardian lost track of it.
+    Failed,
+    /// The server was stopped since it did not come online in time.
+    StartTimeout,
+}
+
+impl From<ExitStatus> for Exit {
+    fn from(status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(0), _) => Self::Success,
+            (Some(code), _) => Self::Code(code),
+            (None, Some(signal)) => Self::Signal(signal),
+            // Only reported for stopped processes, which we don't wait for.
+            (None, None) => Self::Code(status.into_raw()),
+        }
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Success => write!(f, "exited successfully"),
+            Self::Code(code) => write!(f, "exit code: {}", code),
+            Self::Signal(signal) => write!(f, "killed by signal {}", signal),
+            Self::Failed => write!(f, "failed to run"),
+            Self::StartTimeout => write!(f, "did not start in time"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    Stop,
+    /// Launch the server again after waiting.
+    Restart(Duration),
+    /// The server crashed too often.
+    GiveUp,
+}
+
+/// Decides whether to restart the server according to the restart policy,
+/// backing off while crashes repeat.
+#[derive(Debug)]
+pub struct RestartTracker {
+    policy: RestartPolicy,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_crashes: usize,
+    crash_window: Duration,
+    crashes: VecDeque<Instant>,
+}
+
+impl RestartTracker {
+    pub fn new(config: &Restart) -> Self {
+        Self {
+            policy: config.policy,
+            initial_backoff: Duration::from_secs(config.initial_backoff_secs),
+            max_backoff: Duration::from_secs(config.max_backoff_secs),
+            max_crashes: config.max_crashes,
+            crash_window: Duration::from_secs(config.crash_window_secs),
+            crashes: VecDeque::new(),
+        }
+    }
+
+    pub fn on_exit(&mut self, exit: Exit, now: Instant) -> Decision {
+        let crashed = exit != Exit::Success;
+        let restart = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => crashed,
+            RestartPolicy::Always => true,
+        };
+        if !restart {
+            return Decision::Stop;
+        }
+        if !crashed {
+            return Decision::Restart(self.initial_backoff);
+        }
+
+        let window = self.crash_window;
+        self.crashes.retain(|&at| now.duration_since(at) < window);
+        self.crashes.push_back(now);
+        if self.crashes.len() >= self.max_crashes {
+            return Decision::GiveUp;
+        }
+
+        let exp = (self.crashes.len() - 1).min(31) as u32;
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exp)
+            .min(self.max_backoff);
+        Decision::Restart(backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(policy: RestartPolicy) -> Restart {
+        Restart {
+            policy,
+            initial_backoff_secs: 1,
+            max_backoff_secs: 3,
+            max_crashes: 4,
+            crash_window_secs: 60,
+            startup_timeout_secs: 60,
+        }
+    }
+
+    #[test]
+    fn test_exit() {
+        assert_eq!(Exit::Success, Exit::from(ExitStatus::from_raw(0)));
+        assert_eq!(Exit::Code(1), Exit::from(ExitStatus::from_raw(1 << 8)));
+        assert_eq!(Exit::Signal(9), Exit::from(ExitStatus::from_raw(9)));
+    }
+
+    #[test]
+    fn test_policy() {
+        let now = Instant::now();
+
+        let mut never = RestartTracker::new(&config(RestartPolicy::Never));
+        assert_eq!(Decision::Stop, never.on_exit(Exit::Code(1), now));
+
+        let mut on_failure = RestartTracker::new(&config(RestartPolicy::OnFailure));
+        assert_eq!(Decision::Stop, on_failure.on_exit(Exit::Success, now));
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(1)),
+            on_failure.on_exit(Exit::Signal(9), now)
+        );
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(2)),
+            on_failure.on_exit(Exit::Failed, now)
+        );
+
+        let mut always = RestartTracker::new(&config(RestartPolicy::Always));
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(1)),
+            always.on_exit(Exit::Success, now)
+        );
+    }
+
+    #[test]
+    fn test_crash_loop() {
+        let mut tracker = RestartTracker::new(&config(RestartPolicy::OnFailure));
+        let now = Instant::now();
+
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(1)),
+            tracker.on_exit(Exit::Code(1), now)
+        );
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(2)),
+            tracker.on_exit(Exit::Code(1), now + Duration::from_secs(10))
+        );
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(3)),
+            tracker.on_exit(Exit::Code(1), now + Duration::from_secs(20))
+        );
+        // The first crash is out of the window.
+        assert_eq!(
+            Decision::Restart(Duration::from_secs(3)),
+            tracker.on_exit(Exit::Code(1), now + Duration::from_secs(60))
+        );
+        assert_eq!(
+            Decision::GiveUp,
+            tracker.on_exit(Exit::Code(1), now + Duration::from_secs(65))
+        );
+    }
+}
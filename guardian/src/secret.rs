@@ -0,0 +1,47 @@
+use rand::distributions::{Alphanumeric, DistString};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+const PASSWORD_LEN: usize = 32;
+
+/// RCON password shared between the supervisor, which renews it on every
+/// launch, and the monitor. It is kept in memory only and hidden from debug
+/// output so that it does not end up in logs.
+#[derive(Clone, Default)]
+pub struct Password(Arc<RwLock<String>>);
+
+impl Password {
+    /// Replaces the password with a new random one and returns it.
+    pub fn renew(&self) -> String {
+        let password = Alphanumeric.sample_string(&mut rand::thread_rng(), PASSWORD_LEN);
+        *self.0.write().unwrap() = password.clone();
+        password
+    }
+
+    pub fn expose(&self) -> String {
+        self.0.read().unwrap().clone()
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Password(<redacted>)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password() {
+        let password = Password::default();
+        let shared = password.clone();
+
+        let first = password.renew();
+        assert_eq!(PASSWORD_LEN, first.len());
+        assert_eq!(first, shared.expose());
+        assert_ne!(first, password.renew());
+        assert!(!format!("{:?}", shared).contains(&shared.expose()));
+    }
+}
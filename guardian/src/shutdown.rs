@@ -0,0 +1,181 @@
+use crate::job::{JobQueue, Priority};
+use crate::monitor::SessionError;
+use crossbeam_channel::{self as channel, Receiver};
+use guardian::command::{SaveAll, Stop};
+use log::{info, warn};
+use premises_config::v1;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How the server was stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown {
+    /// The server stopped by itself.
+    Graceful(ExitStatus),
+    /// The server stopped after SIGTERM.
+    Terminated(ExitStatus),
+    Killed(ExitStatus),
+}
+
+/// Returns whether the server exited cleanly, including by the SIGTERM sent
+/// to it. The JVM saves the world on SIGTERM and then exits with 143, or
+/// dies of the signal if it exits before the handler is installed.
+fn stopped_cleanly(status: ExitStatus) -> bool {
+    status.success() || status.code() == Some(128 + SIGTERM) || status.signal() == Some(SIGTERM)
+}
+
+impl Shutdown {
+    /// Exit code of the guardian:
+    ///
+    /// - 0 when the server stopped cleanly, whether asked over RCON or by
+    ///   SIGTERM, which is the normal way to stop it when RCON is disabled.
+    /// - 1 when the server exited with an error while stopping.
+    /// - 2 when it had to be killed.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Graceful(status) | Self::Terminated(status) if stopped_cleanly(*status) => 0,
+            Self::Graceful(_) | Self::Terminated(_) => 1,
+            Self::Killed(_) => 2,
+        }
+    }
+
+    pub fn status(&self) -> ExitStatus {
+        match self {
+            Self::Graceful(status) | Self::Terminated(status) | Self::Killed(status) => *status,
+        }
+    }
+}
+
+/// Sends SIGTERM and SIGINT delivered to the guardian to the returned channel.
+pub fn watch_signals() -> io::Result<Receiver<i32>> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    let (tx, rx) = channel::unbounded();
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if tx.send(signal).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+fn terminate(child: &Child) -> io::Result<()> {
+    // SAFETY: kill(2) does not touch memory of this process.
+    if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Stops the server, asking it to save the world and stop over RCON first.
+/// `jobs` is `None` when RCON is disabled, in which case the server is
+/// terminated directly, which also makes it save the world.
+pub fn stop_server(
+    child: &mut Child,
+    jobs: Option<&JobQueue>,
+    config: &v1::Shutdown,
+) -> io::Result<Shutdown> {
+    if let Some(jobs) = jobs {
+        info!("Saving the world and stopping the server");
+        let deadline = Instant::now() + Duration::from_secs(config.grace_period_secs);
+        let _ = jobs.run(&SaveAll { flush: false }, Priority::High);
+        let stop = jobs.run(&Stop, Priority::High);
+
+        // Don't wait for the grace period if the server could not be asked to
+        // stop, e.g. because it is still starting.
+        let result = stop.map(|ticket| ticket.reply.recv_deadline(deadline));
+        match result {
+            Err(err) => warn!("Failed to request stopping the server: {}", err),
+            // Other errors are expected, since the server may close the
+            // connection as soon as it starts stopping.
+            Ok(Ok(Err(
+                err @ (SessionError::Disabled
+                | SessionError::Backoff
+                | SessionError::Connect(_)
+                | SessionError::Authentication(_)),
+            ))) => warn!("Failed to stop the server over RCON: {}", err),
+            _ => {
+                let grace_period = deadline.saturating_duration_since(Instant::now());
+                if let Some(status) = wait_timeout(child, grace_period)? {
+                    return Ok(Shutdown::Graceful(status));
+                }
+                warn!("Server did not stop in the grace period; terminating");
+            }
+        }
+    }
+
+    terminate(child)?;
+    let term_timeout = Duration::from_secs(config.term_timeout_secs);
+    if let Some(status) = wait_timeout(child, term_timeout)? {
+        return Ok(Shutdown::Terminated(status));
+    }
+
+    warn!("Server did not stop after SIGTERM; killing");
+    child.kill()?;
+    Ok(Shutdown::Killed(child.wait()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn config() -> v1::Shutdown {
+        v1::Shutdown {
+            grace_period_secs: 0,
+            term_timeout_secs: 1,
+        }
+    }
+
+    #[test]
+    fn test_stop_server_terminate() {
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let shutdown = stop_server(&mut child, None, &config()).unwrap();
+        assert!(matches!(shutdown, Shutdown::Terminated(_)));
+        assert_eq!(0, shutdown.exit_code());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let exited = |code: i32| ExitStatus::from_raw(code << 8);
+        let signaled = ExitStatus::from_raw;
+        assert_eq!(0, Shutdown::Graceful(exited(0)).exit_code());
+        assert_eq!(1, Shutdown::Graceful(exited(1)).exit_code());
+        assert_eq!(0, Shutdown::Terminated(exited(143)).exit_code());
+        assert_eq!(0, Shutdown::Terminated(signaled(SIGTERM)).exit_code());
+        assert_eq!(1, Shutdown::Terminated(exited(1)).exit_code());
+        assert_eq!(2, Shutdown::Killed(signaled(libc::SIGKILL)).exit_code());
+    }
+
+    #[test]
+    fn test_stop_server_kill() {
+        let mut child = Command::new("sh")
+            .args(["-c", "trap '' TERM; sleep 10"])
+            .spawn()
+            .unwrap();
+        // Let the shell install the trap.
+        thread::sleep(Duration::from_millis(200));
+        let shutdown = stop_server(&mut child, None, &config()).unwrap();
+        assert!(matches!(shutdown, Shutdown::Killed(_)));
+        assert_eq!(2, shutdown.exit_code());
+    }
+}
@@ -0,0 +1,388 @@
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use crate::net::{self, Timeouts};
+
+#[derive(Debug)]
+pub enum Error {
+    Transport(io::Error),
+    Proto(String),
+    Timeout,
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Self::Transport(ref err) => Some(err),
+            Self::Proto(_) | Self::Timeout => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Transport(_) => write!(f, "Failed to communicate with server"),
+            Self::Proto(message) => write!(f, "Protocol error: {}", message),
+            Self::Timeout => write!(f, "Timed out"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if net::is_timeout(&err) {
+            Self::Timeout
+        } else {
+            Self::Transport(err)
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+const PACKET_ID_HANDSHAKE: i32 = 0x00;
+const PACKET_ID_STATUS: i32 = 0x00;
+const PACKET_ID_PING: i32 = 0x01;
+
+const NEXT_STATE_STATUS: i32 = 1;
+
+/// Any version is accepted when only the status is requested.
+const PROTOCOL_VERSION_ANY: i32 = -1;
+
+/// The server rejects packets whose length does not fit in 3 bytes of VarInt.
+const MAX_PACKET_LEN: usize = (1 << 21) - 1;
+
+fn write_var_int(buf: &mut Vec<u8>, value: i32) {
+    let mut value = value as u32;
+    loop {
+        if value & !0x7f == 0 {
+            buf.push(value as u8);
+            return;
+        }
+        buf.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+}
+
+fn read_var_int<R>(reader: &mut R) -> Result<i32>
+where
+    R: Read,
+{
+    let mut value = 0u32;
+    for i in 0..5 {
+        let mut byte = [0; 1];
+        reader.read_exact(&mut byte)?;
+
+        value |= ((byte[0] & 0x7f) as u32) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(value as i32);
+        }
+    }
+
+    Err(Error::Proto("VarInt is too long".to_string()))
+}
+
+fn write_string(buf: &mut Vec<u8>, value: &str) {
+    write_var_int(buf, value.len() as i32);
+    buf.extend_from_slice(value.as_bytes());
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Status {
+    pub version: String,
+    pub protocol: i32,
+    pub motd: String,
+    pub players_online: u32,
+    pub players_max: u32,
+    /// Some of the online players. The server may omit or anonymize them.
+    pub players: Vec<String>,
+    #[serde(rename = "latency_ms", serialize_with = "serialize_millis")]
+    pub latency: Duration,
+}
+
+fn serialize_millis<S>(duration: &Duration, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+#[derive(Deserialize)]
+struct RawVersion {
+    name: String,
+    protocol: i32,
+}
+
+#[derive(Deserialize)]
+struct RawPlayer {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct RawPlayers {
+    max: u32,
+    online: u32,
+    #[serde(default)]
+    sample: Vec<RawPlayer>,
+}
+
+#[derive(Deserialize)]
+struct RawStatus {
+    version: RawVersion,
+    players: RawPlayers,
+    #[serde(default)]
+    description: Value,
+}
+
+/// Flattens a chat component, which may be a plain string or an object with
+/// nested components in "extra".
+fn flatten_text(component: &Value, out: &mut String) {
+    match component {
+        Value::String(text) => out.push_str(text),
+        Value::Array(components) => components.iter().for_each(|c| flatten_text(c, out)),
+        Value::Object(object) => {
+            if let Some(text) = object.get("text") {
+                flatten_text(text, out);
+            }
+            if let Some(extra) = object.get("extra") {
+                flatten_text(extra, out);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn parse_status(json: &str, latency: Duration) -> Result<Status> {
+    let raw: RawStatus = match serde_json::from_str(json) {
+        Ok(raw) => raw,
+        Err(err) => return Err(Error::Proto(format!("Invalid status: {}", err))),
+    };
+
+    let mut motd = String::new();
+    flatten_text(&raw.description, &mut motd);
+
+    Ok(Status {
+        version: raw.version.name,
+        protocol: raw.version.protocol,
+        motd,
+        players_online: raw.players.online,
+        players_max: raw.players.max,
+        players: raw.players.sample.into_iter().map(|p| p.name).collect(),
+        latency,
+    })
+}
+
+/// Client for Server List Ping, which is what the Minecraft client uses to
+/// show servers in the multiplayer menu.
+#[derive(Debug)]
+pub struct SlpClient<T>
+where
+    T: Read + Write,
+{
+    transport: T,
+    host: String,
+    port: u16,
+}
+
+impl SlpClient<TcpStream> {
+    pub fn connect(host: &str, port: u16, timeouts: Timeouts) -> Result<Self> {
+        let transport = net::connect_tcp((host, port), timeouts)?;
+        Ok(Self::new(transport, host, port))
+    }
+}
+
+impl<T> SlpClient<T>
+where
+    T: Read + Write,
+{
+    /// `host` and `port` are sent to the server in the handshake.
+    pub fn new(transport: T, host: &str, port: u16) -> Self {
+        Self {
+            transport,
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    fn send_packet(&mut self, packet_id: i32, data: &[u8]) -> Result<()> {
+        let mut body = Vec::new();
+        write_var_int(&mut body, packet_id);
+        body.extend_from_slice(data);
+
+        let mut buf = Vec::new();
+        write_var_int(&mut buf, body.len() as i32);
+        buf.extend_from_slice(&body);
+
+        self.transport.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    fn recv_packet(&mut self, packet_id: i32) -> Result<Vec<u8>> {
+        let len = read_var_int(&mut self.transport)?;
+        let len = match usize::try_from(len) {
+            Ok(len) if len <= MAX_PACKET_LEN => len,
+            _ => return Err(Error::Proto(format!("Invalid packet length {}", len))),
+        };
+
+        let mut body = io::Cursor::new(vec![0; len]);
+        self.transport.read_exact(body.get_mut())?;
+
+        let actual_id = read_var_int(&mut body)?;
+        if actual_id != packet_id {
+            return Err(Error::Proto(format!(
+                "Expected packet {:#x} but received {:#x}",
+                packet_id, actual_id
+            )));
+        }
+
+        let pos = body.position() as usize;
+        Ok(body.into_inner().split_off(pos))
+    }
+
+    pub fn status(&mut self) -> Result<Status> {
+        let mut handshake = Vec::new();
+        write_var_int(&mut handshake, PROTOCOL_VERSION_ANY);
+        write_string(&mut handshake, &self.host);
+        handshake.extend_from_slice(&self.port.to_be_bytes());
+        write_var_int(&mut handshake, NEXT_STATE_STATUS);
+        self.send_packet(PACKET_ID_HANDSHAKE, &handshake)?;
+
+        self.send_packet(PACKET_ID_STATUS, &[])?;
+        let mut response = io::Cursor::new(self.recv_packet(PACKET_ID_STATUS)?);
+        let json_len = read_var_int(&mut response)?;
+        let json_start = response.position() as usize;
+        let json = match usize::try_from(json_len) {
+            Ok(len) if json_start + len <= response.get_ref().len() => {
+                String::from_utf8_lossy(&response.get_ref()[json_start..json_start + len])
+                    .into_owned()
+            }
+            _ => return Err(Error::Proto(format!("Invalid string length {}", json_len))),
+        };
+
+        let started_at = Instant::now();
+        let payload = 0x7072_656d_6973_6573i64;
+        self.send_packet(PACKET_ID_PING, &payload.to_be_bytes())?;
+        let pong = self.recv_packet(PACKET_ID_PING)?;
+        if pong != payload.to_be_bytes() {
+            return Err(Error::Proto("Pong does not match ping".to_string()));
+        }
+        let latency = started_at.elapsed();
+
+        parse_status(&json, latency)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockTcpStream {
+        readable_buf: &'static [u8],
+        wrote_bytes: Vec<u8>,
+    }
+    impl Read for MockTcpStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.readable_buf.read(buf)
+        }
+    }
+    impl Write for MockTcpStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.wrote_bytes.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_var_int() {
+        for (value, bytes) in [
+            (0, &[0x00][..]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (25565, &[0xdd, 0xc7, 0x01]),
+            (-1, &[0xff, 0xff, 0xff, 0xff, 0x0f]),
+        ] {
+            let mut buf = Vec::new();
+            write_var_int(&mut buf, value);
+            assert_eq!(bytes, buf.as_slice());
+            assert_eq!(value, read_var_int(&mut &buf[..]).unwrap());
+        }
+
+        assert!(matches!(
+            read_var_int(&mut &[0xff; 6][..]),
+            Err(Error::Proto(_))
+        ));
+    }
+
+    #[test]
+    fn test_status() {
+        let mut mock_stream = MockTcpStream {
+            #[rustfmt::skip]
+            readable_buf: &[
+                // status
+                0x61, 0x00, 0x5f,
+                b'{', b'"', b'v', b'e', b'r', b's', b'i', b'o', b'n', b'"', b':',
+                b'{', b'"', b'n', b'a', b'm', b'e', b'"', b':', b'"', b'1', b'.', b'2', b'0', b'.', b'1', b'"', b',',
+                b'"', b'p', b'r', b'o', b't', b'o', b'c', b'o', b'l', b'"', b':', b'7', b'6', b'3', b'}', b',',
+                b'"', b'p', b'l', b'a', b'y', b'e', b'r', b's', b'"', b':',
+                b'{', b'"', b'm', b'a', b'x', b'"', b':', b'2', b'0', b',',
+                b'"', b'o', b'n', b'l', b'i', b'n', b'e', b'"', b':', b'0', b'}', b',',
+                b'"', b'd', b'e', b's', b'c', b'r', b'i', b'p', b't', b'i', b'o', b'n', b'"', b':',
+                b'"', b'h', b'i', b'"', b'}',
+                // pong
+                0x09, 0x01, 0x70, 0x72, 0x65, 0x6d, 0x69, 0x73, 0x65, 0x73,
+            ],
+            wrote_bytes: vec![],
+        };
+
+        let status = {
+            let mut client = SlpClient::new(&mut mock_stream, "localhost", 25565);
+            client.status().unwrap()
+        };
+        assert_eq!("1.20.1", status.version);
+        assert_eq!(763, status.protocol);
+        assert_eq!("hi", status.motd);
+        assert_eq!(0, status.players_online);
+        assert_eq!(20, status.players_max);
+
+        #[rustfmt::skip]
+        assert_eq!(
+            mock_stream.wrote_bytes[..22],
+            [
+                // handshake
+                0x13, 0x00, 0xff, 0xff, 0xff, 0xff, 0x0f,
+                0x09, b'l', b'o', b'c', b'a', b'l', b'h', b'o', b's', b't', 0x63, 0xdd, 0x01,
+                // status request
+                0x01, 0x00,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_status_chat_component() {
+        let status = parse_status(
+            r#"{
+                "version": {"name": "1.20.1", "protocol": 763},
+                "players": {"max": 20, "online": 1, "sample": [{"name": "foo", "id": "x"}]},
+                "description": {"text": "A ", "extra": [{"text": "Minecraft"}, " Server"]}
+            }"#,
+            Duration::from_millis(3),
+        )
+        .unwrap();
+        assert_eq!("A Minecraft Server", status.motd);
+        assert_eq!(vec!["foo"], status.players);
+        assert_eq!(
+            serde_json::json!(3),
+            serde_json::to_value(&status).unwrap()["latency_ms"]
+        );
+    }
+}
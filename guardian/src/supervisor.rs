@@ -0,0 +1,699 @@
+use crate::backup::{Aside, Backups};
+use crate::job::{JobQueue, Priority};
+use crate::lifecycle::{Lifecycle, State};
+use crate::monitor::{Event, EventKind};
+use crate::restart::{Decision, Exit, RestartTracker};
+use crate::secret::Password;
+use crate::shutdown::{self, Shutdown};
+use crossbeam_channel::{self as channel, select, Receiver, Sender};
+use guardian::command::{Message, Say};
+use guardian::console;
+use guardian::properties::Properties;
+use log::{error, info, warn};
+use premises_config::v1;
+use std::fs::{self, create_dir_all, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::net::IpAddr;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Exit code of the guardian when it exits while the server is not running.
+const EXIT_STOPPED: i32 = 0;
+/// Exit code of the guardian when it exits after the server exited
+/// abnormally.
+const EXIT_CRASHED: i32 = 1;
+
+/// Request to control the server process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    Start,
+    Stop,
+    Restart,
+    /// Restore the named backup, restarting the server.
+    Restore(String),
+    /// Stop the server since nobody has been playing on it.
+    IdleStop,
+}
+
+enum Wakeup {
+    Exited(ExitStatus),
+    /// The server did not come online within the startup timeout.
+    StartTimeout,
+    Signal(i32),
+    Control(Control),
+}
+
+/// What to do after supervising a server process.
+enum Outcome {
+    /// The server ended by itself, so the restart policy applies.
+    Exited(Exit),
+    /// Launch the server again right away.
+    Relaunch,
+    /// Exit the guardian with the code.
+    Exit(i32),
+}
+
+fn agree_eula(work_dir: &Path) -> io::Result<()> {
+    create_dir_all(work_dir)?;
+
+    let mut out = File::create(work_dir.join("eula.txt"))?;
+    write!(out, "eula=true")?;
+
+    Ok(())
+}
+
+/// Returns whether RCON only listens on the loopback interface. Vanilla
+/// servers have no setting for the address of RCON, and bind it to
+/// `server-ip`, which is every address when it is empty.
+fn rcon_is_local(props: &Properties) -> bool {
+    match props.get("server-ip") {
+        Some("localhost") => true,
+        Some(ip) => ip.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback()),
+        None => false,
+    }
+}
+
+/// Checks that a `server-ip` set in the config keeps RCON off other hosts.
+pub fn check_server_ip(config: &v1::Config) -> io::Result<()> {
+    let Some(ip) = config.server.properties.other.get("server-ip") else {
+        return Ok(());
+    };
+    let mut props = Properties::default();
+    props.set("server-ip", ip);
+    if rcon_is_local(&props) {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!(
+            "server-ip {:?} would expose RCON to other hosts, since the server binds it \
+             along with the game; use a loopback address behind a proxy",
+            ip
+        ),
+    ))
+}
+
+/// Updates server.properties with the overrides in the config and the
+/// settings the guardian relies on, keeping the other keys as they are.
+fn write_server_properties(config: &v1::Config, rcon_password: &str) -> io::Result<()> {
+    let path = config.work_dir.join("server.properties");
+    let mut props = match fs::read_to_string(&path) {
+        Ok(text) => Properties::parse(&text),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Properties::default(),
+        Err(err) => return Err(err),
+    };
+
+    let overrides = &config.server.properties;
+    for (key, value) in &overrides.other {
+        props.set(key, value);
+    }
+    if let Some(difficulty) = &overrides.difficulty {
+        props.set("difficulty", difficulty);
+    }
+    if let Some(gamemode) = &overrides.gamemode {
+        props.set("gamemode", gamemode);
+    }
+    if let Some(max_players) = overrides.max_players {
+        props.set("max-players", &max_players.to_string());
+    }
+    if let Some(motd) = &overrides.motd {
+        props.set("motd", motd);
+    }
+    if let Some(view_distance) = overrides.view_distance {
+        props.set("view-distance", &view_distance.to_string());
+    }
+
+    // Set last so that overrides cannot break monitoring.
+    props.set("server-port", &config.server.port.to_string());
+    if let Some(query_port) = config.server.query_port {
+        props.set("enable-query", "true");
+        props.set("query.port", &query_port.to_string());
+    }
+    // RCON is always on with a password only the guardian knows, so that
+    // overrides cannot enable it with another one.
+    props.set("enable-rcon", "true");
+    props.set("rcon.port", &config.rcon.port.to_string());
+    props.set("rcon.password", rcon_password);
+    // The server binds RCON to server-ip along with the game, so it listens
+    // on loopback unless the config picks another loopback address.
+    check_server_ip(config)?;
+    if !overrides.other.contains_key("server-ip") {
+        props.set("server-ip", "127.0.0.1");
+    }
+
+    // Write to another file first not to leave a broken file behind. Only the
+    // owner may read it since it contains the password.
+    let tmp_path = config.work_dir.join("server.properties.tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    file.write_all(props.to_string().as_bytes())?;
+    fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600))?;
+    fs::rename(&tmp_path, &path)
+}
+
+/// Echoes the output of the server while turning it into events.
+fn watch_output(
+    child: &mut Child,
+    lifecycle: &Lifecycle,
+    event_tx: &Sender<Event>,
+) -> Vec<JoinHandle<()>> {
+    let stdout = child.stdout.take().unwrap();
+    let stdout_lifecycle = lifecycle.clone();
+    let stdout_tx = event_tx.clone();
+    let stderr = child.stderr.take().unwrap();
+    let stderr_lifecycle = lifecycle.clone();
+    let stderr_tx = event_tx.clone();
+
+    vec![
+        thread::spawn(move || {
+            let result = console::forward_output(stdout, io::stdout(), |ev| {
+                stdout_lifecycle.handle_log_event(&ev);
+                stdout_tx.send(Event::new(EventKind::Console(ev))).unwrap()
+            });
+            if let Err(err) = result {
+                error!("Failed to read the output of the server: {}", err);
+            }
+        }),
+        thread::spawn(move || {
+            let result = console::forward_output(stderr, io::stderr(), |ev| {
+                stderr_lifecycle.handle_log_event(&ev);
+                stderr_tx.send(Event::new(EventKind::Console(ev))).unwrap()
+            });
+            if let Err(err) = result {
+                error!("Failed to read the output of the server: {}", err);
+            }
+        }),
+    ]
+}
+
+/// Launches the server and keeps it running according to the restart policy
+/// and the control requests.
+pub struct Supervisor<'a> {
+    config: &'a v1::Config,
+    lifecycle: Lifecycle,
+    event_tx: Sender<Event>,
+    jobs: JobQueue,
+    rcon_password: Password,
+    signals: Receiver<i32>,
+    control: Receiver<Control>,
+    backups: Backups,
+    restart: RestartTracker,
+    /// Backup restored and the worlds it replaced, until the server starts.
+    restoring: Option<(String, Aside)>,
+}
+
+impl<'a> Supervisor<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config: &'a v1::Config,
+        lifecycle: Lifecycle,
+        event_tx: Sender<Event>,
+        jobs: JobQueue,
+        rcon_password: Password,
+        signals: Receiver<i32>,
+        control: Receiver<Control>,
+        backups: Backups,
+    ) -> Self {
+        Self {
+            config,
+            lifecycle,
+            event_tx,
+            jobs,
+            rcon_password,
+            signals,
+            control,
+            backups,
+            restart: RestartTracker::new(&config.restart),
+            restoring: None,
+        }
+    }
+
+    fn launch(&self) -> io::Result<Child> {
+        // Do not touch the world while it is archived.
+        let _backup = self.backups.hold();
+        self.lifecycle.transition(State::Preparing);
+        agree_eula(&self.config.work_dir)?;
+        // A new password every time limits the damage when one leaks.
+        let rcon_password = self.rcon_password.renew();
+        write_server_properties(self.config, &rcon_password)?;
+
+        let child = Command::new(
+            self.config
+                .java
+                .path
+                .as_ref()
+                .expect("java is resolved before launching"),
+        )
+        .args(&self.config.java.jvm_args)
+        .arg("-jar")
+        .arg(
+            self.config
+                .server
+                .jar
+                .as_ref()
+                .expect("jar is resolved before launching"),
+        )
+        .args(&self.config.server.args)
+        .current_dir(&self.config.work_dir)
+        // Keep signals sent to the guardian from the terminal away from
+        // the server so that it is stopped in order.
+        .process_group(0)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+        self.lifecycle.set_pid(Some(child.id()));
+        self.lifecycle.transition(State::Starting(0));
+        Ok(child)
+    }
+
+    fn wait(&mut self, child: &mut Child) -> io::Result<Wakeup> {
+        let startup_deadline =
+            Instant::now() + Duration::from_secs(self.config.restart.startup_timeout_secs);
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Wakeup::Exited(status));
+            }
+            if self.lifecycle.state().is_starting() && Instant::now() >= startup_deadline {
+                return Ok(Wakeup::StartTimeout);
+            }
+            if self.restoring.is_some() && self.lifecycle.state() == State::Online {
+                let (name, _) = self.restoring.take().unwrap();
+                info!("Restored backup {}", name);
+                self.send_event(EventKind::Restored(name));
+            }
+            select! {
+                recv(self.signals) -> signal => match signal {
+                    Ok(signal) => return Ok(Wakeup::Signal(signal)),
+                    Err(_) => self.close_signals(),
+                },
+                recv(self.control) -> control => match control {
+                    Ok(Control::Start) => info!("Minecraft server is already running"),
+                    Ok(control) => return Ok(Wakeup::Control(control)),
+                    Err(_) => self.control = channel::never(),
+                },
+                default(Duration::from_millis(500)) => (),
+            }
+        }
+    }
+
+    /// Waits for a request to start the server, or for a signal. The server is
+    /// also started after `delay` unless it is stopped in the meantime.
+    fn wait_start(&mut self, delay: Option<Duration>) -> Result<(), i32> {
+        let mut timeout = match delay {
+            Some(delay) => channel::after(delay),
+            None => channel::never(),
+        };
+        loop {
+            select! {
+                recv(self.signals) -> signal => match signal {
+                    Ok(signal) => return Err(signal),
+                    Err(_) => self.close_signals(),
+                },
+                recv(self.control) -> control => match control {
+                    Ok(Control::Start | Control::Restart) => return Ok(()),
+                    Ok(Control::Restore(name)) => {
+                        self.restore(&name);
+                        return Ok(());
+                    }
+                    Ok(Control::IdleStop) => (),
+                    Ok(Control::Stop) => {
+                        timeout = channel::never();
+                        self.lifecycle.transition(State::Stopped);
+                    }
+                    Err(_) => self.control = channel::never(),
+                },
+                recv(timeout) -> _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Stops waiting for signals once they cannot arrive anymore, since a
+    /// closed channel would always be ready.
+    fn close_signals(&mut self) {
+        warn!("Signals are not watched anymore");
+        self.signals = channel::never();
+    }
+
+    fn send_event(&self, kind: EventKind) {
+        self.event_tx.send(Event::new(kind)).unwrap();
+    }
+
+    fn report_failure(&self, message: &str, err: &io::Error) {
+        error!("{}: {}", message, err);
+        self.send_event(EventKind::ProcessFailed(format!("{}: {}", message, err)));
+    }
+
+    /// Restores backup `name` while the server is stopped.
+    fn restore(&mut self, name: &str) {
+        info!("Restoring backup {}", name);
+        match self.backups.restore(name) {
+            Ok(aside) => self.restoring = Some((name.to_string(), aside)),
+            Err(err) => {
+                error!("Failed to restore backup {}: {}", name, err);
+                self.send_event(EventKind::RestoreFailed(format!("{}: {}", name, err)));
+            }
+        }
+    }
+
+    /// Puts back the worlds replaced by a restore when the server exited or
+    /// hung before it finished starting with them. Returns whether it did.
+    fn roll_back(&mut self) -> bool {
+        let Some((name, aside)) = self.restoring.take() else {
+            return false;
+        };
+        warn!(
+            "Minecraft server failed to start with backup {}; rolling back",
+            name
+        );
+        let message = match aside.roll_back() {
+            Ok(_) => format!("{}: Server failed to start", name),
+            Err(err) => {
+                error!("Failed to roll back backup {}: {}", name, err);
+                format!(
+                    "{}: Server failed to start, and rolling back failed: {}",
+                    name, err
+                )
+            }
+        };
+        self.send_event(EventKind::RestoreFailed(message));
+        true
+    }
+
+    /// Stops the server. `None` is returned when it failed, in which case the
+    /// server is killed.
+    fn stop(&self, child: &mut Child, output_threads: Vec<JoinHandle<()>>) -> Option<Shutdown> {
+        self.lifecycle.transition(State::Stopping);
+        let jobs = self.config.rcon.enabled.then_some(&self.jobs);
+        let shutdown = match shutdown::stop_server(child, jobs, &self.config.shutdown) {
+            Ok(shutdown) => Some(shutdown),
+            Err(err) => {
+                self.report_failure("Failed to stop Minecraft server", &err);
+                // The output is closed only once the server is gone.
+                let _ = child.kill();
+                let _ = child.wait();
+                None
+            }
+        };
+        self.lifecycle.set_pid(None);
+        for output_thread in output_threads {
+            output_thread.join().unwrap();
+        }
+        if let Some(shutdown) = shutdown {
+            info!(
+                "Minecraft server stopped ({})",
+                Exit::from(shutdown.status())
+            );
+        }
+        self.lifecycle.transition(State::Stopped);
+        shutdown
+    }
+
+    /// Announces the shutdown and backs up the worlds before stopping a
+    /// server nobody is playing on.
+    fn stop_idle(&self, child: &mut Child, output_threads: Vec<JoinHandle<()>>) {
+        info!("Stopping idle Minecraft server");
+        if self.config.rcon.enabled {
+            match Message::new(&self.config.idle.message) {
+                Ok(message) => {
+                    let _ = self.jobs.run(&Say(message), Priority::High);
+                }
+                Err(err) => warn!("Failed to announce the shutdown: {}", err),
+            }
+        }
+        if self.config.idle.backup {
+            self.backups.run();
+        }
+        self.stop(child, output_threads);
+        self.send_event(EventKind::IdleShutdown);
+    }
+
+    /// Watches a launched server until it exits or is asked to stop.
+    fn supervise(&mut self, mut child: Child) -> Outcome {
+        let output_threads = watch_output(&mut child, &self.lifecycle, &self.event_tx);
+
+        let status = match self.wait(&mut child) {
+            Ok(Wakeup::Exited(status)) => status,
+            Ok(Wakeup::Signal(signal)) => {
+                info!("Received signal {}; stopping Minecraft server", signal);
+                // The world is saved over RCON while the server still runs.
+                if self.config.backup.on_shutdown {
+                    self.backups.run();
+                }
+                let shutdown = self.stop(&mut child, output_threads);
+                return Outcome::Exit(shutdown.map_or(1, |shutdown| shutdown.exit_code()));
+            }
+            Ok(Wakeup::StartTimeout) => {
+                error!(
+                    "Minecraft server did not start in {} s; stopping it",
+                    self.config.restart.startup_timeout_secs
+                );
+                self.stop(&mut child, output_threads);
+                return Outcome::Exited(Exit::StartTimeout);
+            }
+            Ok(Wakeup::Control(Control::Restart)) => {
+                self.stop(&mut child, output_threads);
+                self.lifecycle.transition(State::Restarting);
+                return Outcome::Relaunch;
+            }
+            Ok(Wakeup::Control(Control::Restore(name))) => {
+                self.stop(&mut child, output_threads);
+                self.restore(&name);
+                self.lifecycle.transition(State::Restarting);
+                return Outcome::Relaunch;
+            }
+            Ok(Wakeup::Control(control)) => {
+                if control == Control::IdleStop {
+                    self.stop_idle(&mut child, output_threads);
+                } else {
+                    self.stop(&mut child, output_threads);
+                }
+                if let Err(signal) = self.wait_start(None) {
+                    info!("Received signal {}; exiting", signal);
+                    return Outcome::Exit(EXIT_STOPPED);
+                }
+                return Outcome::Relaunch;
+            }
+            Err(err) => {
+                self.report_failure("Failed to wait for Minecraft server", &err);
+                self.stop(&mut child, output_threads);
+                return Outcome::Exited(Exit::Failed);
+            }
+        };
+        self.lifecycle.set_pid(None);
+        for output_thread in output_threads {
+            output_thread.join().unwrap();
+        }
+        Outcome::Exited(Exit::from(status))
+    }
+
+    /// Runs until the guardian is asked to exit by a signal, returning the
+    /// exit code of the guardian. While the server runs, it tells how the
+    /// server stopped (see `Shutdown::exit_code`). Otherwise it is 0, or 1 if
+    /// the server last exited abnormally, such as when it kept crashing.
+    pub fn run(mut self) -> i32 {
+        loop {
+            let exit = match self.launch() {
+                Ok(child) => match self.supervise(child) {
+                    Outcome::Exited(exit) => exit,
+                    Outcome::Relaunch => continue,
+                    Outcome::Exit(code) => return code,
+                },
+                Err(err) => {
+                    self.report_failure("Failed to launch Minecraft server", &err);
+                    Exit::Failed
+                }
+            };
+
+            if exit == Exit::Success {
+                self.lifecycle.transition(State::Stopped);
+            } else {
+                warn!("Minecraft server exited abnormally ({})", exit);
+                self.lifecycle.transition(State::Crashed(exit));
+            }
+            if self.roll_back() {
+                self.lifecycle.transition(State::Restarting);
+                continue;
+            }
+
+            let delay = match self.restart.on_exit(exit, Instant::now()) {
+                Decision::Stop => None,
+                Decision::Restart(delay) => {
+                    info!("Restarting Minecraft server in {} s", delay.as_secs());
+                    self.lifecycle.transition(State::Restarting);
+                    Some(delay)
+                }
+                Decision::GiveUp => {
+                    error!("Minecraft server keeps crashing; giving up restarting");
+                    self.lifecycle.transition(State::CrashLoop);
+                    None
+                }
+            };
+            if let Err(signal) = self.wait_start(delay) {
+                info!("Received signal {}; exiting", signal);
+                return if exit == Exit::Success {
+                    EXIT_STOPPED
+                } else {
+                    EXIT_CRASHED
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup;
+    use crate::history::History;
+    use crate::job;
+    use premises_config::Config;
+    use std::process;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_write_server_properties() {
+        let work_dir = std::env::temp_dir().join(format!("guardian-test-{}", process::id()));
+        create_dir_all(&work_dir).unwrap();
+        fs::write(
+            work_dir.join("server.properties"),
+            "#Minecraft server properties\nlevel-seed=42\nenable-rcon=false\nmotd=Old\n",
+        )
+        .unwrap();
+
+        let config = serde_json::json!({"v1": {
+            "work_dir": work_dir,
+            "server": {
+                "jar": "server.jar",
+                "properties": {"motd": "New", "other": {
+                    "enable-rcon": "false",
+                    "pvp": "false",
+                    "server-ip": "::1",
+                }},
+            },
+        }});
+        let Config::V1(config) = serde_json::from_value(config).unwrap();
+        write_server_properties(&config, "secret").unwrap();
+
+        let props =
+            Properties::parse(&fs::read_to_string(work_dir.join("server.properties")).unwrap());
+        fs::remove_dir_all(&work_dir).unwrap();
+        assert_eq!(Some("42"), props.get("level-seed"));
+        assert_eq!(Some("New"), props.get("motd"));
+        assert_eq!(Some("false"), props.get("pvp"));
+        assert_eq!(Some("true"), props.get("enable-rcon"));
+        assert_eq!(Some("secret"), props.get("rcon.password"));
+        assert_eq!(Some("25575"), props.get("rcon.port"));
+        assert_eq!(Some("::1"), props.get("server-ip"));
+    }
+
+    #[test]
+    fn test_write_server_properties_binds_loopback() {
+        let work_dir = std::env::temp_dir().join(format!("guardian-loopback-{}", process::id()));
+        create_dir_all(&work_dir).unwrap();
+        fs::write(work_dir.join("server.properties"), "server-ip=\n").unwrap();
+        let config = |other: serde_json::Value| {
+            let config = serde_json::json!({"v1": {
+                "work_dir": work_dir,
+                "server": {"jar": "server.jar", "properties": {"other": other}},
+                "rcon": {"enabled": false},
+            }});
+            let Config::V1(config) = serde_json::from_value(config).unwrap();
+            config
+        };
+
+        write_server_properties(&config(serde_json::json!({})), "secret").unwrap();
+        let props =
+            Properties::parse(&fs::read_to_string(work_dir.join("server.properties")).unwrap());
+        let exposed = config(serde_json::json!({"server-ip": "0.0.0.0"}));
+        let result = write_server_properties(&exposed, "secret");
+        fs::remove_dir_all(&work_dir).unwrap();
+
+        // RCON stays on even when the guardian does not use it.
+        assert_eq!(Some("true"), props.get("enable-rcon"));
+        assert_eq!(Some("secret"), props.get("rcon.password"));
+        assert_eq!(Some("127.0.0.1"), props.get("server-ip"));
+        assert!(check_server_ip(&exposed).is_err());
+        assert_eq!(io::ErrorKind::InvalidInput, result.unwrap_err().kind());
+    }
+
+    #[test]
+    fn test_rcon_is_local() {
+        for (server_ip, local) in [
+            (None, false),
+            (Some(""), false),
+            (Some("0.0.0.0"), false),
+            (Some("192.168.1.2"), false),
+            (Some("127.0.0.1"), true),
+            (Some("::1"), true),
+            (Some("localhost"), true),
+        ] {
+            let mut props = Properties::default();
+            if let Some(server_ip) = server_ip {
+                props.set("server-ip", server_ip);
+            }
+            assert_eq!(local, rcon_is_local(&props), "{:?}", server_ip);
+        }
+    }
+
+    #[test]
+    fn test_roll_back_on_start_timeout() {
+        let work_dir = std::env::temp_dir().join(format!("guardian-hung-{}", process::id()));
+        create_dir_all(work_dir.join("world")).unwrap();
+        fs::write(work_dir.join("world/level.dat"), "old").unwrap();
+        // The shell stands in for a server which never comes online.
+        let config = serde_json::json!({"v1": {
+            "work_dir": work_dir,
+            "server": {"jar": "server.jar"},
+            "java": {"path": "/bin/sh", "jvm_args": ["-c", "exec sleep 10", "sh"]},
+            "rcon": {"enabled": false},
+            "restart": {"startup_timeout_secs": 1},
+            "shutdown": {"term_timeout_secs": 1},
+        }});
+        let Config::V1(config) = serde_json::from_value(config).unwrap();
+
+        let (event_tx, _event_rx) = channel::unbounded();
+        let lifecycle = Lifecycle::new(event_tx.clone());
+        let backups = Backups::new(
+            &config,
+            lifecycle.clone(),
+            None,
+            Arc::new(Mutex::new(History::new(10))),
+            event_tx.clone(),
+        );
+        backups.run();
+        let name = backup::list(&backups.dir()).unwrap()[0].name.clone();
+        fs::write(work_dir.join("world/level.dat"), "new").unwrap();
+
+        let (jobs, _job_rx, _backup_rx) = job::queue();
+        let mut supervisor = Supervisor::new(
+            &config,
+            lifecycle,
+            event_tx,
+            jobs,
+            Password::default(),
+            channel::never(),
+            channel::never(),
+            backups,
+        );
+        supervisor.restore(&name);
+        let restored = fs::read_to_string(work_dir.join("world/level.dat")).unwrap();
+        let outcome = supervisor.supervise(supervisor.launch().unwrap());
+        let rolled_back = supervisor.roll_back();
+        let level = fs::read_to_string(work_dir.join("world/level.dat")).unwrap();
+        fs::remove_dir_all(&work_dir).unwrap();
+
+        assert_eq!("old", restored);
+        assert!(matches!(outcome, Outcome::Exited(Exit::StartTimeout)));
+        assert!(rolled_back);
+        assert_eq!("new", level);
+    }
+}
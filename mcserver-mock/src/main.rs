@@ -238,7 +238,7 @@ mod rcon {
         where
             W: Write,
         {
-            let len = (4 + 4 + 2 + self.payload.as_bytes().len()) as i32;
+            let len = (4 + 4 + 2 + self.payload.len()) as i32;
             let mut writer = BufWriter::new(strm);
             writer.write_all(&len.to_le_bytes()).unwrap();
             writer.write_all(&self.req_id.to_le_bytes()).unwrap();
@@ -269,6 +269,14 @@ mod rcon {
                             .send_to_stream(strm);
                         break Status::Exit;
                     }
+                    ["list"] => {
+                        Packet::new(
+                            req_id,
+                            0,
+                            "There are 0 of a max of 20 players online: ".to_string(),
+                        )
+                        .send_to_stream(strm);
+                    }
                     ["whitelist", "add", user] => {
                         Packet::new(req_id, 0, format!("Added {user} to the whitelist"))
                             .send_to_stream(strm);
@@ -292,7 +300,8 @@ mod rcon {
                 Some(Packet {
                     req_id, pack_type, ..
                 }) => {
-                    Packet::new(req_id, 0, format!("Unknown request {:x}", pack_type));
+                    Packet::new(req_id, 0, format!("Unknown request {:x}", pack_type))
+                        .send_to_stream(strm);
                 }
                 None => break Status::Disconnect,
             };
@@ -347,6 +356,85 @@ mod rcon {
     }
 }
 
+mod status {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    fn read_var_int<R>(strm: &mut R) -> Option<i32>
+    where
+        R: Read,
+    {
+        let mut value = 0u32;
+        for i in 0..5 {
+            let mut buf = [0u8; 1];
+            strm.read_exact(&mut buf).ok()?;
+            value |= ((buf[0] & 0x7f) as u32) << (7 * i);
+            if buf[0] & 0x80 == 0 {
+                return Some(value as i32);
+            }
+        }
+        None
+    }
+
+    fn write_var_int(buf: &mut Vec<u8>, value: i32) {
+        let mut value = value as u32;
+        while value & !0x7f != 0 {
+            buf.push((value & 0x7f) as u8 | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    fn read_packet(strm: &mut TcpStream) -> Option<Vec<u8>> {
+        let len = read_var_int(strm)?;
+        let mut body = vec![0u8; len as usize];
+        strm.read_exact(&mut body).ok()?;
+        Some(body)
+    }
+
+    fn send_packet(strm: &mut TcpStream, body: &[u8]) {
+        let mut buf = Vec::new();
+        write_var_int(&mut buf, body.len() as i32);
+        buf.extend_from_slice(body);
+        strm.write_all(&buf).unwrap();
+    }
+
+    fn handle_client(mut strm: TcpStream, motd: &str, max_players: u32) -> Option<()> {
+        // handshake, then status request
+        read_packet(&mut strm)?;
+        read_packet(&mut strm)?;
+
+        let json = serde_json::json!({
+            "version": {"name": "1.20.1", "protocol": 763},
+            "players": {"max": max_players, "online": 0},
+            "description": {"text": motd},
+        })
+        .to_string();
+        let mut body = vec![0x00];
+        write_var_int(&mut body, json.len() as i32);
+        body.extend_from_slice(json.as_bytes());
+        send_packet(&mut strm, &body);
+
+        // ping is echoed back as is
+        let ping = read_packet(&mut strm)?;
+        send_packet(&mut strm, &ping);
+
+        Some(())
+    }
+
+    pub fn listen(port: u16, motd: String, max_players: u32) {
+        let listener = TcpListener::bind(("0.0.0.0", port)).unwrap();
+
+        thread::spawn(move || loop {
+            let (strm, _) = listener.accept().unwrap();
+            handle_client(strm, &motd, max_players);
+        });
+    }
+}
+
 fn main() {
     println!("Starting net.minecraft.server.Main");
 
@@ -364,6 +452,21 @@ fn main() {
 
     prepare_server();
 
+    status::listen(
+        server_props
+            .get("server-port")
+            .map(|v| v.parse::<u16>().unwrap())
+            .unwrap_or(25565),
+        server_props
+            .get("motd")
+            .cloned()
+            .unwrap_or("A Minecraft Server".to_string()),
+        server_props
+            .get("max-players")
+            .map(|v| v.parse::<u32>().unwrap())
+            .unwrap_or(20),
+    );
+
     rcon::listen(rcon_passwd, rcon_port);
 
     stop_server();
@@ -1,4 +1,473 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct Config {}
+pub struct Config {
+    /// Directory the server runs in, where the world and server.properties are.
+    pub work_dir: PathBuf,
+    pub server: Server,
+    #[serde(default)]
+    pub java: Java,
+    #[serde(default)]
+    pub rcon: Rcon,
+    #[serde(default)]
+    pub restart: Restart,
+    #[serde(default)]
+    pub shutdown: Shutdown,
+    #[serde(default)]
+    pub api: Api,
+    #[serde(default)]
+    pub backup: Backup,
+    #[serde(default)]
+    pub download: Download,
+    #[serde(default)]
+    pub idle: Idle,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Server {
+    /// Path to the server jar. The jar of `version` is downloaded if unset.
+    #[serde(default)]
+    pub jar: Option<PathBuf>,
+    #[serde(default)]
+    pub version: ServerVersion,
+    /// Arguments passed to the server after the jar.
+    #[serde(default = "default_server_args")]
+    pub args: Vec<String>,
+    #[serde(default = "default_server_port")]
+    pub port: u16,
+    /// Port to list players with Query when RCON is disabled.
+    #[serde(default)]
+    pub query_port: Option<u16>,
+    #[serde(default)]
+    pub properties: ServerProperties,
+}
+
+/// Minecraft version to download the server of.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServerVersion {
+    #[default]
+    LatestRelease,
+    LatestSnapshot,
+    /// Version id such as "1.20.1".
+    #[serde(untagged)]
+    Id(String),
+}
+
+/// Values written to server.properties. Keys not set here are left as they
+/// are in the file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ServerProperties {
+    pub difficulty: Option<String>,
+    pub gamemode: Option<String>,
+    pub max_players: Option<u32>,
+    pub motd: Option<String>,
+    pub view_distance: Option<u32>,
+    /// Any other keys, written as they are.
+    #[serde(default)]
+    pub other: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Java {
+    /// Java to run the server with. If unset, it is picked by the version of
+    /// the server among the runtimes in `JAVA_HOME` and `search_dirs`.
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    /// Directories containing JDKs or JREs, such as `/usr/lib/jvm`.
+    #[serde(default = "default_java_search_dirs")]
+    pub search_dirs: Vec<PathBuf>,
+    /// Whether a newer Java than the server needs may be picked when no
+    /// runtime of the same major version is found.
+    #[serde(default)]
+    pub allow_newer: bool,
+    #[serde(default)]
+    pub memory: Memory,
+    #[serde(default)]
+    pub flags: FlagPreset,
+    /// Passed after the generated arguments, so that they take precedence.
+    #[serde(default)]
+    pub jvm_args: Vec<String>,
+}
+
+impl Default for Java {
+    fn default() -> Self {
+        Self {
+            path: None,
+            search_dirs: default_java_search_dirs(),
+            allow_newer: false,
+            memory: Memory::default(),
+            flags: FlagPreset::default(),
+            jvm_args: Vec::new(),
+        }
+    }
+}
+
+/// Heap size of the server, passed as both `-Xms` and `-Xmx` unless they are
+/// in `jvm_args`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Memory {
+    /// Heap size in MiB. If unset, the total memory of the system except
+    /// `reserved_mb` is used, which must leave at least 512 MiB.
+    #[serde(default)]
+    pub heap_mb: Option<u64>,
+    /// Memory in MiB left for the system and for the JVM outside the heap.
+    #[serde(default = "default_reserved_memory_mb")]
+    pub reserved_mb: u64,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            heap_mb: None,
+            reserved_mb: default_reserved_memory_mb(),
+        }
+    }
+}
+
+/// Set of well-known JVM flags to tune garbage collection with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlagPreset {
+    #[default]
+    None,
+    /// Aikar's flags for G1, see https://docs.papermc.io/paper/aikars-flags.
+    Aikar,
+}
+
+/// RCON settings. The server always runs RCON on a loopback address with a
+/// password generated on every launch, so that only the guardian can use it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Rcon {
+    /// Whether the guardian runs commands and lists players over RCON. Query
+    /// is used to list players otherwise.
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_rcon_port")]
+    pub port: u16,
+}
+
+impl Default for Rcon {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            port: default_rcon_port(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    Never,
+    OnFailure,
+    Always,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Restart {
+    #[serde(default = "default_restart_policy")]
+    pub policy: RestartPolicy,
+    /// Delay before the first restart, doubled on each crash in the window.
+    #[serde(default = "default_initial_backoff_secs")]
+    pub initial_backoff_secs: u64,
+    #[serde(default = "default_max_backoff_secs")]
+    pub max_backoff_secs: u64,
+    /// Number of crashes within `crash_window_secs` to give up restarting.
+    #[serde(default = "default_max_crashes")]
+    pub max_crashes: usize,
+    #[serde(default = "default_crash_window_secs")]
+    pub crash_window_secs: u64,
+    /// Time the server may take to come online before it is stopped as hung,
+    /// which counts as a crash.
+    #[serde(default = "default_startup_timeout_secs")]
+    pub startup_timeout_secs: u64,
+}
+
+impl Default for Restart {
+    fn default() -> Self {
+        Self {
+            policy: default_restart_policy(),
+            initial_backoff_secs: default_initial_backoff_secs(),
+            max_backoff_secs: default_max_backoff_secs(),
+            max_crashes: default_max_crashes(),
+            crash_window_secs: default_crash_window_secs(),
+            startup_timeout_secs: default_startup_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Shutdown {
+    /// Time to wait for the server to stop by itself before terminating it.
+    #[serde(default = "default_grace_period_secs")]
+    pub grace_period_secs: u64,
+    /// Time to wait after SIGTERM before killing the server.
+    #[serde(default = "default_term_timeout_secs")]
+    pub term_timeout_secs: u64,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            grace_period_secs: default_grace_period_secs(),
+            term_timeout_secs: default_term_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Api {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_api_bind")]
+    pub bind: String,
+    /// Bearer token required on requests if set.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+impl Default for Api {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bind: default_api_bind(),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    /// Directory to store backups in, relative to `work_dir`.
+    #[serde(default = "default_backup_dir")]
+    pub dir: PathBuf,
+    /// Interval of scheduled backups. No backups are scheduled if unset.
+    #[serde(default)]
+    pub interval_secs: Option<u64>,
+    /// Whether to back up the world before the server is stopped on shutdown.
+    #[serde(default = "default_true")]
+    pub on_shutdown: bool,
+    #[serde(default)]
+    pub retention: Retention,
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Self {
+            dir: default_backup_dir(),
+            interval_secs: None,
+            on_shutdown: true,
+            retention: Retention::default(),
+        }
+    }
+}
+
+/// Backups to keep. A backup is kept if any of the rules keeps it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Retention {
+    /// Number of the latest backups to keep.
+    #[serde(default = "default_keep_last")]
+    pub keep_last: usize,
+    /// Number of days to keep the latest backup of.
+    #[serde(default = "default_keep_daily")]
+    pub keep_daily: usize,
+    /// Number of weeks to keep the latest backup of.
+    #[serde(default = "default_keep_weekly")]
+    pub keep_weekly: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            keep_last: default_keep_last(),
+            keep_daily: default_keep_daily(),
+            keep_weekly: default_keep_weekly(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Download {
+    /// URL the version manifest is served under, at
+    /// `/mc/game/version_manifest_v2.json`.
+    #[serde(default = "default_download_base_url")]
+    pub base_url: String,
+    /// Directory to keep downloaded server jars in, relative to `work_dir`.
+    #[serde(default = "default_download_cache_dir")]
+    pub cache_dir: PathBuf,
+}
+
+impl Default for Download {
+    fn default() -> Self {
+        Self {
+            base_url: default_download_base_url(),
+            cache_dir: default_download_cache_dir(),
+        }
+    }
+}
+
+/// Stopping the server when nobody plays on it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Idle {
+    /// Time without players online after which the server is stopped. The
+    /// server is never stopped for being idle if unset.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Message broadcast before stopping.
+    #[serde(default = "default_idle_message")]
+    pub message: String,
+    /// Whether to back up the world before stopping.
+    #[serde(default = "default_true")]
+    pub backup: bool,
+}
+
+impl Default for Idle {
+    fn default() -> Self {
+        Self {
+            timeout_secs: None,
+            message: default_idle_message(),
+            backup: true,
+        }
+    }
+}
+
+fn default_server_args() -> Vec<String> {
+    vec!["nogui".to_string()]
+}
+
+fn default_server_port() -> u16 {
+    25565
+}
+
+fn default_java_search_dirs() -> Vec<PathBuf> {
+    vec![PathBuf::from("/usr/lib/jvm")]
+}
+
+fn default_reserved_memory_mb() -> u64 {
+    1024
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_rcon_port() -> u16 {
+    25575
+}
+
+fn default_restart_policy() -> RestartPolicy {
+    RestartPolicy::OnFailure
+}
+
+fn default_initial_backoff_secs() -> u64 {
+    1
+}
+
+fn default_max_backoff_secs() -> u64 {
+    60
+}
+
+fn default_max_crashes() -> usize {
+    5
+}
+
+fn default_crash_window_secs() -> u64 {
+    600
+}
+
+fn default_startup_timeout_secs() -> u64 {
+    600
+}
+
+fn default_grace_period_secs() -> u64 {
+    60
+}
+
+fn default_term_timeout_secs() -> u64 {
+    10
+}
+
+fn default_api_bind() -> String {
+    "127.0.0.1:8521".to_string()
+}
+
+fn default_backup_dir() -> PathBuf {
+    PathBuf::from("backups")
+}
+
+fn default_keep_last() -> usize {
+    5
+}
+
+fn default_keep_daily() -> usize {
+    7
+}
+
+fn default_keep_weekly() -> usize {
+    4
+}
+
+fn default_download_base_url() -> String {
+    "https://piston-meta.mojang.com".to_string()
+}
+
+fn default_download_cache_dir() -> PathBuf {
+    PathBuf::from("jars")
+}
+
+fn default_idle_message() -> String {
+    "Stopping the server since no players are online".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlagPreset, RestartPolicy, ServerVersion};
+    use crate::Config;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_defaults() {
+        let config: Config = serde_json::from_str(
+            r#"{"v1": {"work_dir": "/srv/minecraft", "server": {"jar": "server.jar"}}}"#,
+        )
+        .unwrap();
+        let Config::V1(config) = config;
+
+        assert_eq!("/srv/minecraft", config.work_dir.to_str().unwrap());
+        assert_eq!(vec!["nogui"], config.server.args);
+        assert_eq!(25565, config.server.port);
+        assert_eq!(None, config.server.query_port);
+        assert_eq!(ServerVersion::LatestRelease, config.server.version);
+        assert_eq!(None, config.java.path);
+        assert_eq!(vec![PathBuf::from("/usr/lib/jvm")], config.java.search_dirs);
+        assert!(config.java.jvm_args.is_empty());
+        assert_eq!(None, config.java.memory.heap_mb);
+        assert_eq!(1024, config.java.memory.reserved_mb);
+        assert_eq!(FlagPreset::None, config.java.flags);
+        assert!(config.rcon.enabled);
+        assert_eq!(25575, config.rcon.port);
+        assert_eq!(RestartPolicy::OnFailure, config.restart.policy);
+        assert_eq!(600, config.restart.startup_timeout_secs);
+        assert_eq!("backups", config.backup.dir.to_str().unwrap());
+        assert_eq!(None, config.backup.interval_secs);
+        assert_eq!(5, config.backup.retention.keep_last);
+        assert_eq!(None, config.idle.timeout_secs);
+    }
+
+    #[test]
+    fn test_restart_policy() {
+        let policy: RestartPolicy = serde_json::from_str(r#""on-failure""#).unwrap();
+        assert_eq!(RestartPolicy::OnFailure, policy);
+    }
+
+    #[test]
+    fn test_server_version() {
+        let version: ServerVersion = serde_json::from_str(r#""latest-snapshot""#).unwrap();
+        assert_eq!(ServerVersion::LatestSnapshot, version);
+        let version: ServerVersion = serde_json::from_str(r#""1.20.1""#).unwrap();
+        assert_eq!(ServerVersion::Id("1.20.1".to_string()), version);
+    }
+}